@@ -0,0 +1,60 @@
+use chess::{get_bishop_moves, get_king_moves, get_knight_moves, get_rook_moves, Board, Color, Piece, Square};
+
+// attack maps for the eval, as plain u64 bitboards
+
+pub const FILE_A: u64 = 0x0101010101010101;
+
+// squares pawns of `color` attack, all of them at once
+pub fn pawn_attacks(color: Color, pawns: u64) -> u64 {
+    let not_a = !FILE_A;
+    let not_h = !(FILE_A << 7);
+    match color {
+        Color::White => ((pawns & not_a) << 7) | ((pawns & not_h) << 9),
+        Color::Black => ((pawns & not_a) >> 9) | ((pawns & not_h) >> 7),
+    }
+}
+
+// every square `color` attacks with pieces and pawns
+pub fn attacks(board: &Board, color: Color) -> u64 {
+    let ours = *board.color_combined(color);
+    let occupied = *board.combined();
+    let mut attacked = pawn_attacks(color, (board.pieces(Piece::Pawn) & ours).0);
+    for square in board.pieces(Piece::Knight) & ours {
+        attacked |= get_knight_moves(square).0;
+    }
+    for square in (board.pieces(Piece::Bishop) | board.pieces(Piece::Queen)) & ours {
+        attacked |= get_bishop_moves(square, occupied).0;
+    }
+    for square in (board.pieces(Piece::Rook) | board.pieces(Piece::Queen)) & ours {
+        attacked |= get_rook_moves(square, occupied).0;
+    }
+    attacked | get_king_moves(board.king_square(color)).0
+}
+
+// king steps from one square to the other
+pub fn distance(a: Square, b: Square) -> i32 {
+    let files = a.get_file().to_index().abs_diff(b.get_file().to_index());
+    let ranks = a.get_rank().to_index().abs_diff(b.get_rank().to_index());
+    files.max(ranks) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn attack_maps() {
+        let bits = |squares: &[Square]| squares.iter().fold(0u64, |bits, square| bits | 1 << square.to_index());
+        // pawns on the edge don't wrap round to the other side
+        assert_eq!(pawn_attacks(Color::White, bits(&[Square::A2, Square::H2])), bits(&[Square::B3, Square::G3]));
+        assert_eq!(pawn_attacks(Color::Black, bits(&[Square::A7, Square::H7])), bits(&[Square::B6, Square::G6]));
+        // the rook stops at the first piece in the way, whichever side it belongs to
+        let board = Board::from_str("4k3/8/8/8/8/8/P7/R3K3 w - - 0 1").unwrap();
+        let expected = bits(&[Square::B3, Square::A2, Square::B1, Square::C1, Square::D1, Square::E1])
+            | bits(&[Square::D1, Square::D2, Square::E2, Square::F2, Square::F1]);
+        assert_eq!(attacks(&board, Color::White), expected);
+        assert_eq!(distance(Square::A1, Square::H8), 7);
+        assert_eq!(distance(Square::E4, Square::G3), 2);
+    }
+}
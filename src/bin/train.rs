@@ -0,0 +1,429 @@
+use chess::{Board, Color, ALL_PIECES};
+use chessengine_plsbuild::nnue::{self, Network, HIDDEN_ALIGN, INPUTS, MAX_HIDDEN, QA, QB};
+use chessengine_plsbuild::rng::Rng;
+use chessengine_plsbuild::selfplay;
+use chessengine_plsbuild::tuner;
+use std::fs;
+use std::thread;
+use std::time::Instant;
+
+// trains a network for the nnue eval (see nnue.rs) on the positions from `selfplay`, no python
+// needed. the float network mirrors the quantized one: 768 -> N (x2, shared) -> 1 with the
+// hidden layer clipped to 0..1, and its output is the score in units of nnue::SCALE. the loss is
+// the squared error between sigmoid(output) and a blend of the game result and the search score
+// (sigmoid(score / SCALE)), both from the side to move's point of view. adam does the updates.
+//
+// every epoch writes a checkpoint (the float weights and adam's state, -resume picks it up) and
+// the quantized network for the engine
+
+const USAGE: &str = "usage: train <data file> [-epochs N] [-batch N] [-lr X] [-lrdrop N] [-hidden N] [-lambda X] \
+[-val X] [-threads N] [-seed N] [-out FILE] [-checkpoint FILE] [-resume FILE]";
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"PLSCKPT1";
+const BETA1: f32 = 0.9;
+const BETA2: f32 = 0.999;
+const EPSILON: f32 = 1e-8;
+// quantized weights have to fit in an i16
+const MAX_FEATURE_WEIGHT: f32 = i16::MAX as f32 / QA as f32;
+const MAX_OUTPUT_WEIGHT: f32 = i16::MAX as f32 / QB as f32;
+
+struct Settings {
+    path: String,
+    epochs: usize,
+    batch: usize,
+    lr: f32,
+    // the learning rate goes down 10x every this many epochs, 0 never
+    lr_drop: usize,
+    hidden: usize,
+    // how much of the target is the search score, the rest is the game result
+    lambda: f32,
+    // part of the data kept back to measure the validation loss
+    validation: f64,
+    threads: usize,
+    seed: u64,
+    out: String,
+    checkpoint: String,
+    resume: Option<String>,
+}
+
+// a position as the network's active inputs, ours first, then theirs
+struct Entry {
+    features: [[u16; 32]; 2],
+    count: u8,
+    // expected score for the side to move, 0 to 1
+    target: f32,
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+fn to_entry(board: &Board, white_score: Option<i32>, white_result: f32, lambda: f32) -> Entry {
+    let side = board.side_to_move();
+    let mut features = [[0u16; 32]; 2];
+    let mut count = 0;
+    for color in [Color::White, Color::Black] {
+        for piece in ALL_PIECES {
+            for square in *board.pieces(piece) & *board.color_combined(color) {
+                if count == 32 {
+                    break;
+                }
+                features[0][count] = nnue::feature_index(side, color, piece, square) as u16;
+                features[1][count] = nnue::feature_index(!side, color, piece, square) as u16;
+                count += 1;
+            }
+        }
+    }
+    let result = if side == Color::White { white_result } else { 1.0 - white_result };
+    // labelled positions without a score train on the result alone
+    let target = match white_score {
+        Some(score) => {
+            let score = if side == Color::White { score } else { -score };
+            lambda * sigmoid(score as f32 / nnue::SCALE as f32) + (1.0 - lambda) * result
+        }
+        None => result,
+    };
+    Entry {
+        features,
+        count: count as u8,
+        target,
+    }
+}
+
+fn load(settings: &Settings) -> Result<Vec<Entry>, String> {
+    let text = fs::read_to_string(&settings.path).map_err(|e| format!("can't read {}: {}", settings.path, e))?;
+    let entries: Vec<Entry> = text
+        .lines()
+        .filter_map(|line| match selfplay::parse_record(line) {
+            Some(record) => Some(to_entry(&record.board, Some(record.score), record.result, settings.lambda)),
+            None => tuner::parse_sample(line).map(|sample| to_entry(&sample.board, None, sample.result as f32, settings.lambda)),
+        })
+        .collect();
+    if entries.is_empty() {
+        return Err(format!("no positions in {}", settings.path));
+    }
+    Ok(entries)
+}
+
+// the float network, all weights in one vector so adam can treat them alike:
+// feature weights [768][N], feature biases [N], output weights [2N], output bias
+struct Trainer {
+    hidden: usize,
+    params: Vec<f32>,
+    // adam's moving averages of the gradient and its square
+    m: Vec<f32>,
+    v: Vec<f32>,
+    step: u64,
+    epoch: usize,
+}
+
+impl Trainer {
+    fn new(hidden: usize, rng: &mut Rng) -> Self {
+        let size = (INPUTS + 3) * hidden + 1;
+        let mut params = vec![0.0; size];
+        let mut uniform = |range: f32| (rng.next_f64() as f32 * 2.0 - 1.0) * range;
+        for weight in &mut params[..INPUTS * hidden] {
+            *weight = uniform(0.1);
+        }
+        for bias in &mut params[INPUTS * hidden..(INPUTS + 1) * hidden] {
+            *bias = uniform(0.1) + 0.1;
+        }
+        for weight in &mut params[(INPUTS + 1) * hidden..size - 1] {
+            *weight = uniform(1.0 / (hidden as f32).sqrt());
+        }
+        Trainer {
+            hidden,
+            params,
+            m: vec![0.0; size],
+            v: vec![0.0; size],
+            step: 0,
+            epoch: 0,
+        }
+    }
+
+    fn feature_biases(&self) -> usize {
+        INPUTS * self.hidden
+    }
+
+    fn output_weights(&self) -> usize {
+        (INPUTS + 1) * self.hidden
+    }
+
+    fn output_bias(&self) -> usize {
+        self.params.len() - 1
+    }
+
+    // hidden layer before clipping for both points of view, and the output
+    fn forward(&self, entry: &Entry, accumulators: &mut [Vec<f32>; 2]) -> f32 {
+        let hidden = self.hidden;
+        let biases = &self.params[self.feature_biases()..self.feature_biases() + hidden];
+        let mut output = self.params[self.output_bias()];
+        for (side, accumulator) in accumulators.iter_mut().enumerate() {
+            accumulator.copy_from_slice(biases);
+            for feature in &entry.features[side][..entry.count as usize] {
+                let column = &self.params[*feature as usize * hidden..(*feature as usize + 1) * hidden];
+                for (value, weight) in accumulator.iter_mut().zip(column) {
+                    *value += weight;
+                }
+            }
+            let weights = &self.params[self.output_weights() + side * hidden..self.output_weights() + (side + 1) * hidden];
+            output += accumulator.iter().zip(weights).map(|(value, weight)| value.clamp(0.0, 1.0) * weight).sum::<f32>();
+        }
+        output
+    }
+
+    // adds the gradient of the batch's summed loss to `gradient`, returns the summed loss
+    fn backward(&self, batch: &[Entry], gradient: &mut [f32]) -> f64 {
+        let hidden = self.hidden;
+        let mut accumulators = [vec![0.0; hidden], vec![0.0; hidden]];
+        let mut hidden_gradient = vec![0.0; hidden];
+        let mut loss = 0.0;
+        for entry in batch {
+            let prediction = sigmoid(self.forward(entry, &mut accumulators));
+            let error = prediction - entry.target;
+            loss += (error * error) as f64;
+            let output_gradient = 2.0 * error * prediction * (1.0 - prediction);
+            gradient[self.output_bias()] += output_gradient;
+            for (side, accumulator) in accumulators.iter().enumerate() {
+                let weights = self.output_weights() + side * hidden;
+                for (j, value) in accumulator.iter().enumerate() {
+                    gradient[weights + j] += output_gradient * value.clamp(0.0, 1.0);
+                    // nothing flows back through a clipped neuron
+                    hidden_gradient[j] = if *value > 0.0 && *value < 1.0 { output_gradient * self.params[weights + j] } else { 0.0 };
+                }
+                // the biases are where the column of an always active 769th input would be
+                for feature in entry.features[side][..entry.count as usize].iter().chain([&(INPUTS as u16)]) {
+                    let start = *feature as usize * hidden;
+                    for (g, h) in gradient[start..start + hidden].iter_mut().zip(&hidden_gradient) {
+                        *g += h;
+                    }
+                }
+            }
+        }
+        loss
+    }
+
+    // mean loss over `entries`, split over threads
+    fn loss(&self, entries: &[Entry], threads: usize) -> f64 {
+        let chunk = entries.len().div_ceil(threads).max(1);
+        let total: f64 = thread::scope(|scope| {
+            let workers: Vec<_> = entries
+                .chunks(chunk)
+                .map(|part| {
+                    scope.spawn(move || {
+                        let mut accumulators = [vec![0.0; self.hidden], vec![0.0; self.hidden]];
+                        part.iter()
+                            .map(|entry| ((sigmoid(self.forward(entry, &mut accumulators)) - entry.target) as f64).powi(2))
+                            .sum::<f64>()
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|worker| worker.join().expect("trainer thread panicked")).sum()
+        });
+        total / entries.len() as f64
+    }
+
+    // one adam step on a batch, returns the batch's summed loss
+    fn train_batch(&mut self, batch: &[Entry], gradients: &mut [Vec<f32>], lr: f32) -> f64 {
+        let chunk = batch.len().div_ceil(gradients.len()).max(1);
+        let trainer = &*self;
+        let loss: f64 = thread::scope(|scope| {
+            let workers: Vec<_> = batch
+                .chunks(chunk)
+                .zip(gradients.iter_mut())
+                .map(|(part, gradient)| {
+                    scope.spawn(move || {
+                        gradient.fill(0.0);
+                        trainer.backward(part, gradient)
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|worker| worker.join().expect("trainer thread panicked")).sum()
+        });
+
+        self.step += 1;
+        let correction1 = 1.0 - BETA1.powi(self.step as i32);
+        let correction2 = 1.0 - BETA2.powi(self.step as i32);
+        let used = batch.len().div_ceil(chunk);
+        let scale = 1.0 / batch.len() as f32;
+        for i in 0..self.params.len() {
+            let g = gradients[..used].iter().map(|gradient| gradient[i]).sum::<f32>() * scale;
+            self.m[i] = BETA1 * self.m[i] + (1.0 - BETA1) * g;
+            self.v[i] = BETA2 * self.v[i] + (1.0 - BETA2) * g * g;
+            let update = lr * (self.m[i] / correction1) / ((self.v[i] / correction2).sqrt() + EPSILON);
+            let limit = if i < self.output_weights() { MAX_FEATURE_WEIGHT } else { MAX_OUTPUT_WEIGHT };
+            self.params[i] = (self.params[i] - update).clamp(-limit, limit);
+        }
+        loss
+    }
+
+    // rounds everything to the integer network the engine runs
+    fn quantize(&self) -> Result<Network, String> {
+        let round = |values: &[f32], scale: i32| values.iter().map(|value| (value * scale as f32).round() as i16).collect::<Vec<_>>();
+        Network::new(
+            self.hidden,
+            round(&self.params[..self.feature_biases()], QA),
+            round(&self.params[self.feature_biases()..self.output_weights()], QA),
+            round(&self.params[self.output_weights()..self.output_bias()], QB),
+            (self.params[self.output_bias()] * (QA * QB) as f32).round() as i32,
+        )
+    }
+
+    fn save_checkpoint(&self, path: &str) -> Result<(), String> {
+        let mut bytes = CHECKPOINT_MAGIC.to_vec();
+        bytes.extend_from_slice(&(self.hidden as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.epoch as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.step.to_le_bytes());
+        for values in [&self.params, &self.m, &self.v] {
+            for value in values.iter() {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        fs::write(path, bytes).map_err(|e| format!("can't write {}: {}", path, e))
+    }
+
+    fn load_checkpoint(path: &str) -> Result<Trainer, String> {
+        let bytes = fs::read(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+        let header = CHECKPOINT_MAGIC.len() + 16;
+        if bytes.len() < header || &bytes[..CHECKPOINT_MAGIC.len()] != CHECKPOINT_MAGIC {
+            return Err(format!("{} is not a checkpoint", path));
+        }
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let hidden = u32_at(8) as usize;
+        let epoch = u32_at(12) as usize;
+        let step = u64::from_le_bytes(bytes[16..24].try_into().unwrap());
+        let size = (INPUTS + 3) * hidden + 1;
+        if bytes.len() != header + 3 * size * 4 {
+            return Err(format!("{} has the wrong size for {} hidden neurons", path, hidden));
+        }
+        let floats: Vec<f32> = bytes[header..].chunks_exact(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect();
+        Ok(Trainer {
+            hidden,
+            params: floats[..size].to_vec(),
+            m: floats[size..2 * size].to_vec(),
+            v: floats[2 * size..].to_vec(),
+            step,
+            epoch,
+        })
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let settings = match parse_args(&args) {
+        Ok(settings) => settings,
+        Err(e) => {
+            println!("{}", e);
+            println!("{}", USAGE);
+            return;
+        }
+    };
+    if let Err(e) = train(&settings) {
+        println!("{}", e);
+    }
+}
+
+fn train(settings: &Settings) -> Result<(), String> {
+    let mut rng = Rng::new(settings.seed);
+    let mut entries = load(settings)?;
+    // shuffled once so the validation set is a fair sample, then the training part every epoch
+    for i in (1..entries.len()).rev() {
+        entries.swap(i, rng.below(i + 1));
+    }
+    let validation_size = (entries.len() as f64 * settings.validation) as usize;
+    let (validation, training) = entries.split_at_mut(validation_size);
+    println!("{} training positions, {} validation positions", training.len(), validation.len());
+    if training.is_empty() {
+        return Err("nothing left to train on".to_string());
+    }
+
+    let mut trainer = match &settings.resume {
+        Some(path) => {
+            let trainer = Trainer::load_checkpoint(path)?;
+            println!("resuming from {} after epoch {}", path, trainer.epoch);
+            trainer
+        }
+        None => Trainer::new(settings.hidden, &mut rng),
+    };
+    let mut gradients = vec![vec![0.0; trainer.params.len()]; settings.threads];
+    while trainer.epoch < settings.epochs {
+        let timer = Instant::now();
+        let drops = trainer.epoch.checked_div(settings.lr_drop).unwrap_or(0);
+        let lr = settings.lr * 0.1f32.powi(drops as i32);
+        for i in (1..training.len()).rev() {
+            training.swap(i, rng.below(i + 1));
+        }
+        let mut loss = 0.0;
+        for batch in training.chunks(settings.batch) {
+            loss += trainer.train_batch(batch, &mut gradients, lr);
+        }
+        trainer.epoch += 1;
+        let validation_loss = if validation.is_empty() {
+            String::from("-")
+        } else {
+            format!("{:.6}", trainer.loss(validation, settings.threads))
+        };
+        println!(
+            "epoch {} lr {} loss {:.6} validation loss {} ({:.1}s)",
+            trainer.epoch,
+            lr,
+            loss / training.len() as f64,
+            validation_loss,
+            timer.elapsed().as_secs_f64()
+        );
+        trainer.save_checkpoint(&settings.checkpoint)?;
+        trainer.quantize()?.save(&settings.out)?;
+    }
+    println!("network written to {}", settings.out);
+    Ok(())
+}
+
+fn parse_args(args: &[String]) -> Result<Settings, String> {
+    let path = args.first().ok_or("no data file")?.clone();
+    let mut settings = Settings {
+        path,
+        epochs: 10,
+        batch: 16384,
+        lr: 0.001,
+        lr_drop: 0,
+        hidden: 128,
+        lambda: 0.5,
+        validation: 0.05,
+        threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        seed: 1,
+        out: "net.nnue".to_string(),
+        checkpoint: "checkpoint.bin".to_string(),
+        resume: None,
+    };
+    for pair in args[1..].chunks(2) {
+        let flag = pair[0].as_str();
+        let value = pair.get(1).ok_or_else(|| format!("{} needs a value", flag))?;
+        let number = || value.parse::<usize>().map_err(|_| format!("not a number: {}", value));
+        let fraction = || {
+            value
+                .parse::<f32>()
+                .ok()
+                .filter(|x| (0.0..=1.0).contains(x))
+                .ok_or_else(|| format!("not between 0 and 1: {}", value))
+        };
+        match flag {
+            "-epochs" => settings.epochs = number()?,
+            "-batch" => settings.batch = number()?.max(1),
+            "-lr" => settings.lr = value.parse().map_err(|_| format!("not a number: {}", value))?,
+            "-lrdrop" => settings.lr_drop = number()?,
+            "-hidden" => settings.hidden = number()?,
+            "-lambda" => settings.lambda = fraction()?,
+            "-val" => settings.validation = fraction()? as f64,
+            "-threads" => settings.threads = number()?.max(1),
+            "-seed" => settings.seed = number()? as u64,
+            "-out" => settings.out = value.clone(),
+            "-checkpoint" => settings.checkpoint = value.clone(),
+            "-resume" => settings.resume = Some(value.clone()),
+            _ => return Err(format!("unknown flag {}", flag)),
+        }
+    }
+    if settings.hidden == 0 || settings.hidden > MAX_HIDDEN || !settings.hidden.is_multiple_of(HIDDEN_ALIGN) {
+        return Err(format!("-hidden must be a multiple of {} up to {}", HIDDEN_ALIGN, MAX_HIDDEN));
+    }
+    Ok(settings)
+}
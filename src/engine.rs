@@ -1,17 +1,14 @@
-use chess::{Board, ChessMove, Color, MoveGen, Piece};
+use chess::{Board, ChessMove, MoveGen};
 use crate::eval;
-use std::collections::HashMap;
-
-// A simple transposition table entry
-struct TTEntry {
-    depth: u32,
-    eval: i32,
-    flag: u8, // 0 = exact, 1 = lower bound, 2 = upper bound
-    best_move: Option<ChessMove>,
-}
+use crate::nnue::{AccumulatorStack, Network};
+use crate::pawns::PawnTable;
+use crate::timeman::{Clock, TimeManager};
+use crate::tt::{TTEntry, TranspositionTable};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-// Add this as a global or pass it as a parameter
-type TranspositionTable = HashMap<u64, TTEntry>;
 
 // kill moves and shti
 struct KillerMoves {
@@ -45,45 +42,629 @@ impl KillerMoves {
         self.moves[ply][0] == Some(*m) || self.moves[ply][1] == Some(*m)
     }
 }
-pub fn engine_move(board: Board, ai_color: Color) -> ChessMove {
-    // return search(board, ai_color, 3);
-    let mut killers = KillerMoves::new(10);
-    let mut tt: TranspositionTable = HashMap::with_capacity(1_000_000);
-        match search(board, ai_color, 6, i32::MIN + 1, i32::MAX - 10000, true, &mut tt, &mut killers).0 {
-            Some(best_move) => best_move,
-            None => {
-                // Fallback to any legal move if no best move found
-                println!("No best move found, falling back to any legal move.");
-                MoveGen::new_legal(&board).next().expect("no legal moves available")
+
+// mate scores are stored relative to the node rather than the root, so they stay right
+// when the same position turns up at another ply (or in the next search)
+fn score_to_tt(score: i32, ply: u32) -> i32 {
+    if score >= MATE_SCORE - MAX_PLY as i32 {
+        score + ply as i32
+    } else if score <= -(MATE_SCORE - MAX_PLY as i32) {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: u32) -> i32 {
+    if score >= MATE_SCORE - MAX_PLY as i32 {
+        score - ply as i32
+    } else if score <= -(MATE_SCORE - MAX_PLY as i32) {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+// triangular pv table: line `ply` holds the best line found from that ply on. a node that
+// raises alpha copies its move plus the child's line into its own, so once the root is done
+// line 0 is the principal variation
+struct PvTable {
+    lines: Vec<Vec<ChessMove>>,
+}
+
+impl PvTable {
+    fn new() -> Self {
+        PvTable {
+            lines: vec![Vec::new(); MAX_PLY as usize + 1],
+        }
+    }
+
+    // called on entering a node, whatever was there belongs to an older line
+    fn clear(&mut self, ply: u32) {
+        if let Some(line) = self.lines.get_mut(ply as usize) {
+            line.clear();
+        }
+    }
+
+    fn update(&mut self, ply: u32, m: ChessMove) {
+        let ply = ply as usize;
+        if ply >= MAX_PLY as usize {
+            return;
+        }
+        let (head, tail) = self.lines.split_at_mut(ply + 1);
+        let line = &mut head[ply];
+        line.clear();
+        line.push(m);
+        line.extend_from_slice(&tail[0]);
+    }
+
+    fn root(&self) -> &[ChessMove] {
+        &self.lines[0]
+    }
+}
+
+// what lives longer than one search: the transposition table is kept for the whole game so
+// a new search (or a ponder hit) starts with everything the last one learned
+#[derive(Clone)]
+pub struct Engine {
+    tt: Arc<TranspositionTable>,
+    // search threads (lazy smp), the first one is the main thread
+    threads: usize,
+    // evaluates with this instead of the classical eval when set
+    network: Option<Arc<Network>>,
+}
+
+pub const MAX_THREADS: usize = 256;
+pub const MAX_MULTI_PV: usize = 256;
+
+// lazy smp: helper threads skip some depths so they don't all search the same iteration
+// at the same time. helper n uses entry n % 20 of these (the old stockfish tables)
+const SKIP_SIZE: [u32; 20] = [1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4];
+const SKIP_PHASE: [u32; 20] = [0, 1, 0, 1, 2, 3, 0, 1, 2, 3, 4, 5, 0, 1, 2, 3, 4, 5, 6, 7];
+
+// how the outside world talks to a running search
+#[derive(Default)]
+pub struct SearchSignals {
+    // end the search now, keeping the best fully searched move
+    pub stop: AtomicBool,
+    // searching on the opponent's time: no time limits until this goes back to false (ponderhit)
+    pub pondering: AtomicBool,
+}
+
+// everything one search needs to carry around between nodes
+struct SearchState<'a> {
+    tt: &'a TranspositionTable,
+    killers: KillerMoves,
+    pv: PvTable,
+    // this thread's nodes, and how many of them were already added to the shared count
+    nodes: u64,
+    flushed_nodes: u64,
+    shared_nodes: &'a AtomicU64,
+    ply: u32,
+    timer: Instant,
+    // when our clock started running: the start of the search, or the ponder hit
+    clock_start: Instant,
+    limits: SearchLimits,
+    time: TimeManager,
+    // raised from outside (another thread) to end the search early or to end pondering
+    signals: &'a SearchSignals,
+    was_pondering: bool,
+    // set once a limit runs out mid-iteration, everything unwinds without touching the tt
+    stopped: bool,
+    // the first iteration always runs to completion so there is a move to play
+    can_stop: bool,
+    // None for the classical eval
+    nnue: Option<AccumulatorStack<'a>>,
+    // pawn structure scores for the classical eval
+    pawns: PawnTable,
+}
+
+impl<'a> SearchState<'a> {
+    fn new(tt: &'a TranspositionTable, limits: SearchLimits, time: TimeManager, signals: &'a SearchSignals, shared_nodes: &'a AtomicU64) -> Self {
+        SearchState {
+            tt,
+            killers: KillerMoves::new(MAX_PLY as usize),
+            pv: PvTable::new(),
+            nodes: 0,
+            flushed_nodes: 0,
+            shared_nodes,
+            ply: 0,
+            timer: Instant::now(),
+            clock_start: Instant::now(),
+            limits,
+            time,
+            signals,
+            was_pondering: signals.pondering.load(Ordering::Relaxed),
+            stopped: false,
+            can_stop: false,
+            nnue: None,
+            pawns: PawnTable::new(),
+        }
+    }
+
+    // call right after making a move (or a null move) and going up a ply
+    fn push_position(&mut self, before: &Board, after: &Board) {
+        if let Some(nnue) = &mut self.nnue {
+            nnue.push(self.ply as usize, before, after);
+        }
+    }
+
+    // negamax: every score in the search, this one included, is for the side to move
+    fn static_eval(&mut self, board: &Board) -> i32 {
+        match &self.nnue {
+            Some(nnue) => nnue.evaluate(self.ply as usize, board.side_to_move()),
+            None => eval::evaluate_cached(*board, &mut self.pawns),
+        }
+    }
+
+    // polled from inside the search, only looks at the clock every few thousand nodes
+    fn should_stop(&mut self) -> bool {
+        if self.stopped {
+            return true;
+        }
+        if !self.can_stop {
+            return false;
+        }
+        if self.signals.stop.load(Ordering::Relaxed) {
+            self.stopped = true;
+            return true;
+        }
+        if !self.nodes.is_multiple_of(2048) {
+            return false;
+        }
+        let total_nodes = self.flush_nodes();
+        let out_of_nodes = self.limits.nodes.is_some_and(|nodes| total_nodes >= nodes);
+        let out_of_time = match self.time_used() {
+            Some(used) => self.time.hard_limit().is_some_and(|hard| used >= hard),
+            None => false,
+        };
+        self.stopped = out_of_nodes || out_of_time;
+        self.stopped
+    }
+
+    // adds this thread's new nodes to the count shared by all threads, returns the total
+    fn flush_nodes(&mut self) -> u64 {
+        let new_nodes = self.nodes - self.flushed_nodes;
+        self.flushed_nodes = self.nodes;
+        self.shared_nodes.fetch_add(new_nodes, Ordering::Relaxed) + new_nodes
+    }
+
+    // how much of our own clock this search has used, None while pondering (it's free).
+    // a ponder hit restarts the count since the opponent's clock was running until then
+    fn time_used(&mut self) -> Option<Duration> {
+        if self.signals.pondering.load(Ordering::Relaxed) {
+            return None;
+        }
+        if self.was_pondering {
+            self.was_pondering = false;
+            self.clock_start = Instant::now();
+        }
+        Some(self.clock_start.elapsed())
+    }
+}
+
+// when to stop searching. anything left as None is not a limit
+#[derive(Clone, Default)]
+pub struct SearchLimits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+    // our side's clock, the time manager splits it into per-move limits
+    pub clock: Option<Clock>,
+    // time lost talking to the gui on every move, kept off the clock
+    pub move_overhead: Duration,
+    // only these root moves are considered when not empty (uci `go searchmoves`)
+    pub search_moves: Vec<ChessMove>,
+    // keep the result back until stopped, even if the search runs out of things to do
+    pub infinite: bool,
+    // start out pondering (see SearchSignals::pondering)
+    pub ponder: bool,
+    // how many of the best root moves get a line of their own (0 and 1 both mean just the best)
+    pub multi_pv: usize,
+}
+
+// one ranked root move with its score and the line the search expects after it
+#[derive(Clone)]
+pub struct PvLine {
+    pub score: i32,
+    pub pv: Vec<ChessMove>,
+}
+
+impl PvLine {
+    pub fn mate_in(&self) -> Option<i32> {
+        mate_in(self.score)
+    }
+
+    pub fn pv_string(&self) -> String {
+        pv_string(&self.pv)
+    }
+}
+
+// what a finished search hands back to whoever asked for it (the game loop or a protocol)
+#[derive(Clone)]
+pub struct SearchResult {
+    pub best_move: Option<ChessMove>,
+    // the reply we expect to best_move, what we ponder on
+    pub ponder_move: Option<ChessMove>,
+    // the line the search expects, starting with best_move
+    pub pv: Vec<ChessMove>,
+    // best first, one per multipv line. the first one is best_move/score/pv again
+    pub lines: Vec<PvLine>,
+    pub score: i32,
+    pub depth: u32,
+    pub nodes: u64,
+    pub elapsed: Duration,
+    // permille of the tt filled by this search
+    pub hashfull: u32,
+}
+
+impl SearchResult {
+    pub fn nps(&self) -> u64 {
+        let millis = self.elapsed.as_millis() as u64;
+        if millis == 0 {
+            return self.nodes;
+        }
+        self.nodes * 1000 / millis
+    }
+
+    // the pv as space separated uci moves
+    pub fn pv_string(&self) -> String {
+        pv_string(&self.pv)
+    }
+
+    pub fn mate_in(&self) -> Option<i32> {
+        mate_in(self.score)
+    }
+}
+
+// Some(n) when the score is a forced mate, n moves (not plies) away. negative when we get mated
+fn mate_in(score: i32) -> Option<i32> {
+    if score.abs() < MATE_SCORE - MAX_PLY as i32 {
+        return None;
+    }
+    let plies = MATE_SCORE - score.abs();
+    let moves = (plies + 1) / 2;
+    Some(if score > 0 { moves } else { -moves })
+}
+
+fn pv_string(pv: &[ChessMove]) -> String {
+    pv.iter().map(|m| m.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+pub const MAX_PLY: u32 = 64;
+pub const MATE_SCORE: i32 = 100000;
+// how long the interactive game thinks per move when nobody asked for anything else
+pub const DEFAULT_MOVE_TIME: Duration = Duration::from_secs(3);
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine {
+            tt: Arc::new(TranspositionTable::default()),
+            threads: 1,
+            network: None,
+        }
+    }
+
+    // None goes back to the classical eval
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        self.network = network;
+    }
+
+    // throws the old table away, so only between searches
+    pub fn set_hash_size(&mut self, megabytes: usize) {
+        self.tt = Arc::new(TranspositionTable::new(megabytes));
+    }
+
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.clamp(1, MAX_THREADS);
+    }
+
+    // forget everything from the last game
+    pub fn new_game(&self) {
+        self.tt.clear();
+    }
+
+    // iterative deepening: search depth 1, 2, 3... until a limit runs out. the tt and killers carry
+    // over between iterations so every iteration starts on the previous best move.
+    // `on_iteration` sees every iteration that finished, the last one is what gets returned.
+    // raising `signals.stop` ends the search with the best move of the last finished iteration.
+    // with more than one thread the helpers search the same position into the shared tt and the
+    // main thread (which does all the reporting and time keeping) picks up what they found
+    pub fn search(&self, board: Board, limits: &SearchLimits, signals: &SearchSignals, on_iteration: impl FnMut(&SearchResult)) -> SearchResult {
+        self.tt.new_search();
+        let shared_nodes = AtomicU64::new(0);
+        // helpers have no limits of their own, they stop when the main thread is done
+        let helper_signals = SearchSignals::default();
+        let helper_limits = SearchLimits {
+            search_moves: limits.search_moves.clone(),
+            ..SearchLimits::default()
+        };
+        let mut result = thread::scope(|scope| {
+            for helper in 1..self.threads {
+                let (helper_signals, helper_limits, shared_nodes) = (&helper_signals, &helper_limits, &shared_nodes);
+                scope.spawn(move || {
+                    let mut state = SearchState::new(&self.tt, helper_limits.clone(), TimeManager::unlimited(), helper_signals, shared_nodes);
+                    // helpers may stop at any time, the main thread is the one that must finish depth 1
+                    state.can_stop = true;
+                    state.nnue = self.network.as_deref().map(|network| AccumulatorStack::new(network, &board));
+                    iterative_deepening(board, &mut state, helper, |_| {});
+                });
             }
+            let time = match (limits.movetime, &limits.clock) {
+                (Some(movetime), _) => TimeManager::fixed(movetime, limits.move_overhead),
+                (None, Some(clock)) => TimeManager::from_clock(clock, limits.move_overhead, MoveGen::new_legal(&board).len()),
+                (None, None) => TimeManager::unlimited(),
+            };
+            let mut state = SearchState::new(&self.tt, limits.clone(), time, signals, &shared_nodes);
+            state.nnue = self.network.as_deref().map(|network| AccumulatorStack::new(network, &board));
+            let mut result = iterative_deepening(board, &mut state, 0, on_iteration);
+            // uci doesn't allow a bestmove during `go infinite` or while pondering before the gui
+            // says stop (or ponderhit), even when there is nothing left to search
+            while (limits.infinite || signals.pondering.load(Ordering::Relaxed)) && !signals.stop.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(5));
+            }
+            helper_signals.stop.store(true, Ordering::Relaxed);
+            state.flush_nodes();
+            result.elapsed = state.timer.elapsed();
+            result
+        });
+        // every thread has flushed its nodes by now
+        result.nodes = shared_nodes.load(Ordering::Relaxed);
+        result
+    }
+
+    // runs search() on a worker thread so the caller can keep reading input.
+    // `on_finish` runs on the worker once the search is over, before join() returns
+    pub fn spawn_search(
+        &self,
+        board: Board,
+        limits: SearchLimits,
+        mut on_iteration: impl FnMut(&SearchResult) + Send + 'static,
+        on_finish: impl FnOnce(&SearchResult) + Send + 'static,
+    ) -> SearchHandle {
+        let signals = Arc::new(SearchSignals::default());
+        signals.pondering.store(limits.ponder, Ordering::Relaxed);
+        let thread_signals = Arc::clone(&signals);
+        let engine = self.clone();
+        let thread = thread::spawn(move || {
+            let result = engine.search(board, &limits, &thread_signals, &mut on_iteration);
+            on_finish(&result);
+            result
+        });
+        SearchHandle { signals, thread }
+    }
+}
+
+// the iterative deepening loop every search thread runs. only the main thread (thread 0) uses
+// the time manager and reports iterations, helpers skip depths according to SKIP_SIZE/SKIP_PHASE
+fn iterative_deepening(board: Board, state: &mut SearchState, thread_id: usize, mut on_iteration: impl FnMut(&SearchResult)) -> SearchResult {
+    let max_depth = state.limits.depth.unwrap_or(MAX_PLY).clamp(1, MAX_PLY);
+    let search_moves = state.limits.search_moves.clone();
+    let multi_pv = state.limits.multi_pv.clamp(1, MAX_MULTI_PV);
+    // multipv searches the root moves one by one, best lines of the last iteration first
+    let mut root_moves: Vec<ChessMove> = if search_moves.is_empty() {
+        MoveGen::new_legal(&board).collect()
+    } else {
+        search_moves.clone()
+    };
+    let mut result = SearchResult {
+        best_move: None,
+        ponder_move: None,
+        pv: Vec::new(),
+        lines: Vec::new(),
+        score: 0,
+        depth: 0,
+        nodes: 0,
+        elapsed: Duration::ZERO,
+        hashfull: 0,
+    };
+
+    for depth in 1..=max_depth {
+        if thread_id > 0 {
+            let skip = (thread_id - 1) % SKIP_SIZE.len();
+            if !((depth + SKIP_PHASE[skip]) / SKIP_SIZE[skip]).is_multiple_of(2) {
+                continue;
+            }
+        }
+        let searching_lines = multi_pv > 1 && root_moves.len() > 1;
+        let (lines, score) = if searching_lines {
+            let lines = search_multi_pv(board, depth, &mut root_moves, multi_pv, state);
+            let score = lines.first().map_or(0, |line| line.score);
+            (lines, score)
+        } else {
+            let (best_move, score) = if search_moves.is_empty() {
+                search(board, depth, -MATE_SCORE - 1, MATE_SCORE + 1, true, state)
+            } else {
+                search_root_moves(board, depth, &search_moves, state)
+            };
+            let line = best_move.map(|m| PvLine {
+                score,
+                pv: principal_variation(&board, m, state),
+            });
+            (line.into_iter().collect(), score)
+        };
+        if state.stopped {
+            // half-searched iterations are thrown away
+            break;
+        }
+        let pv = lines.first().map(|line| line.pv.clone()).unwrap_or_default();
+        let best_move = pv.first().copied();
+        result = SearchResult {
+            best_move,
+            ponder_move: pv.get(1).copied(),
+            pv,
+            lines,
+            score,
+            depth,
+            nodes: state.flush_nodes(),
+            elapsed: state.timer.elapsed(),
+            hashfull: state.tt.hashfull(),
+        };
+        on_iteration(&result);
+        state.can_stop = true;
+
+        // no point going deeper with nothing to move, or with a mate no longer than the depth
+        // just searched, there can't be a quicker one. a longer mate may still get shorter and
+        // a lost position may find a way out. `go infinite` and pondering asked to keep going,
+        // and with multipv the other lines still want their depth
+        let proven_mate = !searching_lines && MATE_SCORE - score <= depth as i32;
+        let keep_going = state.limits.infinite || state.signals.pondering.load(Ordering::Relaxed);
+        if best_move.is_none() || (proven_mate && !keep_going) {
+            break;
+        }
+        state.time.update(best_move, score);
+        let out_of_time = state.time_used().is_some_and(|used| state.time.soft_limit_reached(used));
+        if out_of_time || state.signals.stop.load(Ordering::Relaxed) {
+            break;
+        }
+    }
+    result
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+// the pv after searching the root, `best_move` is what the root search came back with
+fn principal_variation(board: &Board, best_move: ChessMove, state: &SearchState) -> Vec<ChessMove> {
+    let mut pv = state.pv.root().to_vec();
+    if pv.first() != Some(&best_move) {
+        // the root was answered straight from the tt
+        pv = vec![best_move];
+    }
+    extend_pv(board, &mut pv, state.tt);
+    pv
+}
+
+// tt cutoffs cut the pv table's lines short, so keep following the tt's best moves from where
+// the line ends. stops at the first illegal move or at a position that is already on the line
+fn extend_pv(board: &Board, pv: &mut Vec<ChessMove>, tt: &TranspositionTable) {
+    let mut board = *board;
+    let mut seen = vec![board.get_hash()];
+    for m in pv.iter() {
+        board = board.make_move_new(*m);
+        seen.push(board.get_hash());
+    }
+    while pv.len() < MAX_PLY as usize {
+        let Some(m) = tt.get(board.get_hash()).and_then(|entry| entry.best_move).filter(|m| board.legal(*m)) else {
+            break;
+        };
+        board = board.make_move_new(m);
+        if seen.contains(&board.get_hash()) {
+            break;
+        }
+        seen.push(board.get_hash());
+        pv.push(m);
+    }
+}
+
+// a search running on its own thread
+pub struct SearchHandle {
+    signals: Arc<SearchSignals>,
+    thread: JoinHandle<SearchResult>,
+}
+
+impl SearchHandle {
+    // ask the search to finish up, it keeps the last fully searched move
+    pub fn stop(&self) {
+        self.signals.stop.store(true, Ordering::Relaxed);
+    }
+
+    // the opponent played the move we were pondering on, from now on it's our clock
+    pub fn ponderhit(&self) {
+        self.signals.pondering.store(false, Ordering::Relaxed);
+    }
+
+    // wait for the search to end (by itself or after stop()) and hand back its result
+    pub fn join(self) -> SearchResult {
+        self.thread.join().expect("search thread panicked")
+    }
+}
+
+// plain full-window search over a restricted set of root moves (uci `go searchmoves`)
+fn search_root_moves(board: Board, depth: u32, root_moves: &[ChessMove], state: &mut SearchState) -> (Option<ChessMove>, i32) {
+    let mut alpha = -MATE_SCORE - 1;
+    let beta = MATE_SCORE + 1;
+    let mut best_move = None;
+    state.pv.clear(state.ply);
+    for m in root_moves.iter().filter(|m| board.legal(**m)) {
+        let new_board = board.make_move_new(*m);
+        state.ply += 1;
+        state.push_position(&board, &new_board);
+        let (_, evaluation) = search(new_board, depth - 1, -beta, -alpha, true, state);
+        state.ply -= 1;
+        if state.stopped {
+            break;
+        }
+        if best_move.is_none() || -evaluation > alpha {
+            alpha = -evaluation;
+            best_move = Some(*m);
+            state.pv.update(state.ply, *m);
+        }
+    }
+    (best_move, alpha)
+}
+
+// multipv: search the root `count` times, each time without the moves already found, so line n
+// is the best move that isn't in lines 1..n. the best lines are moved to the front of
+// `root_moves` for the next iteration
+fn search_multi_pv(board: Board, depth: u32, root_moves: &mut Vec<ChessMove>, count: usize, state: &mut SearchState) -> Vec<PvLine> {
+    let mut remaining = root_moves.clone();
+    let mut lines = Vec::new();
+    while lines.len() < count && !remaining.is_empty() {
+        let (best_move, score) = search_root_moves(board, depth, &remaining, state);
+        if state.stopped {
+            return lines;
+        }
+        let Some(best_move) = best_move else {
+            break;
+        };
+        remaining.retain(|m| *m != best_move);
+        lines.push(PvLine {
+            score,
+            pv: principal_variation(&board, best_move, state),
+        });
     }
+    *root_moves = lines.iter().map(|line| line.pv[0]).chain(remaining).collect();
+    lines
 }
+
 // how the fuck do you add null move pruning
-fn search(board: Board, ai_color: Color, depth: u32, mut alpha: i32, mut beta: i32, is_pv_node: bool, tt: &mut TranspositionTable, killers: &mut KillerMoves) -> (Option<ChessMove>, i32) {
+fn search(board: Board, depth: u32, mut alpha: i32, mut beta: i32, is_pv_node: bool, state: &mut SearchState) -> (Option<ChessMove>, i32) {
+    if state.should_stop() {
+        return (None, 0);
+    }
+    state.nodes += 1;
+    state.pv.clear(state.ply);
     let orig_alpha = alpha;
     let zobrist_hash = board.get_hash();
     // transposition table
-    if let Some(tt_entry) = tt.get(&zobrist_hash) {
-        if tt_entry.depth >= depth {
-            match tt_entry.flag {
-                0 => return (tt_entry.best_move, tt_entry.eval), // Exact score
-                1 => alpha = alpha.max(tt_entry.eval),           // Lower bound
-                2 => beta = beta.min(tt_entry.eval),             // Upper bound
-                _ => {}
-            }
-            
-            if alpha >= beta {
-                return (tt_entry.best_move, tt_entry.eval);
-            }
+    let tt_hit = state.tt.get(zobrist_hash);
+    let tt_move = tt_hit.and_then(|tt_entry| tt_entry.best_move);
+    if let Some(tt_entry) = tt_hit
+        && tt_entry.depth >= depth {
+        let tt_eval = score_from_tt(tt_entry.eval, state.ply);
+        match tt_entry.flag {
+            0 => return (tt_entry.best_move, tt_eval), // Exact score
+            1 => alpha = alpha.max(tt_eval),           // Lower bound
+            2 => beta = beta.min(tt_eval),             // Upper bound
+            _ => {}
+        }
+        
+        if alpha >= beta {
+            return (tt_entry.best_move, tt_eval);
         }
     }
     if depth == 0 {
-        return quiescence_search(board, ai_color, alpha, beta, killers);
+        return quiescence_search(board, alpha, beta, state);
     }
     
     // Terminal position checks
     if board.status() == chess::BoardStatus::Checkmate {
-        return (None, if board.side_to_move() == ai_color { -100000 } else { 100000 });
+        // mated, so as bad as it gets for the side to move
+        return (None, -(MATE_SCORE - state.ply as i32));
     }
     if board.status() == chess::BoardStatus::Stalemate {
         return (None, 0);
@@ -92,10 +673,15 @@ fn search(board: Board, ai_color: Color, depth: u32, mut alpha: i32, mut beta: i
     // Null move pruning (only in non-PV nodes)
     if !is_pv_node && depth >= 3 && board.checkers().popcnt() == 0 {
         let r = if depth > 6 { 3 } else { 2 }; // Dynamic reduction
-        let null_board = board.null_move();
-        if null_board.is_some() {
-            let (_, null_eval) = search(null_board.unwrap(), ai_color, depth - 1 - r, -beta, -beta + 1, false, tt, killers);
+        if let Some(null_board) = board.null_move() {
+            state.ply += 1;
+            state.push_position(&board, &null_board);
+            let (_, null_eval) = search(null_board, depth - 1 - r, -beta, -beta + 1, false, state);
+            state.ply -= 1;
             let check_eval = -null_eval;
+            if state.stopped {
+                return (None, 0);
+            }
             if check_eval >= beta {
                 return (None, beta);
             }
@@ -105,7 +691,7 @@ fn search(board: Board, ai_color: Color, depth: u32, mut alpha: i32, mut beta: i
     let mut best_eval = i32::MIN;
     let mut best_move = None;
     let movegen = MoveGen::new_legal(&board);
-    let moves = order_moves(movegen, &board, killers, depth as usize);
+    let moves = order_moves(movegen, &board, &state.killers, depth as usize, tt_move);
 
     
     let mut search_pv = true; // Flag to indicate if we're searching the first move
@@ -114,6 +700,7 @@ fn search(board: Board, ai_color: Color, depth: u32, mut alpha: i32, mut beta: i
         i += 1;
         let mut new_board = Board::default();
         board.make_move(m, &mut new_board);
+        state.tt.prefetch(new_board.get_hash());
         
         let mut evaluation;
         let is_quiet = board.checkers().popcnt() == 0 && 
@@ -124,20 +711,26 @@ fn search(board: Board, ai_color: Color, depth: u32, mut alpha: i32, mut beta: i
             } else {
                 depth - 1
             };
+        state.ply += 1;
+        state.push_position(&board, &new_board);
         // PVS: first move gets full window, others get zero window
         if search_pv {
             // Full window search for first move or PV node
-            (_, evaluation) = search(new_board, ai_color, next_depth, -beta, -alpha, true, tt, killers);
+            (_, evaluation) = search(new_board, next_depth, -beta, -alpha, true, state);
             search_pv = false; // No longer searching first move
         } else {
             // Zero window search for non-first moves
-            (_, evaluation) = search(new_board, ai_color, next_depth, -alpha - 1, -alpha, false, tt, killers);
+            (_, evaluation) = search(new_board, next_depth, -alpha - 1, -alpha, false, state);
             
             // Re-search with full window if it might improve alpha and we're in a PV node
             if is_pv_node && -evaluation > alpha && -evaluation < beta {
-                (_, evaluation) = search(new_board, ai_color, next_depth, -beta, -alpha, true, tt, killers);
+                (_, evaluation) = search(new_board, next_depth, -beta, -alpha, true, state);
             }
         }
+        state.ply -= 1;
+        if state.stopped {
+            return (None, 0);
+        }
         
         let negated_eval = -evaluation;
         
@@ -148,11 +741,12 @@ fn search(board: Board, ai_color: Color, depth: u32, mut alpha: i32, mut beta: i
             
             if negated_eval > alpha {
                 alpha = negated_eval;
+                state.pv.update(state.ply, m);
                 
                 if alpha >= beta {
                     // Beta cutoff
                     if board.piece_on(m.get_dest()).is_none() {
-                        killers.add_killer(m, depth as usize);
+                        state.killers.add_killer(m, depth as usize);
                     }
                     break;
                 }
@@ -162,7 +756,7 @@ fn search(board: Board, ai_color: Color, depth: u32, mut alpha: i32, mut beta: i
     
     // Handle no legal moves
     if best_move.is_none() {
-        return (None, eval::evaluate(board, ai_color));
+        return (None, state.static_eval(&board));
     }
     let flag = if best_eval <= orig_alpha {
         2 // Upper bound (fail-low)
@@ -172,19 +766,24 @@ fn search(board: Board, ai_color: Color, depth: u32, mut alpha: i32, mut beta: i
         0 // Exact score
     };
     
-    tt.insert(zobrist_hash, TTEntry {
+    state.tt.insert(zobrist_hash, TTEntry {
         depth,
-        eval: best_eval,
+        eval: score_to_tt(best_eval, state.ply),
         flag,
         best_move,
     });
     (best_move, best_eval)
 }
 
-fn order_moves(moves: MoveGen, board: &Board, killers: &KillerMoves, ply: usize) -> Vec<ChessMove> {
+fn order_moves(moves: MoveGen, board: &Board, killers: &KillerMoves, ply: usize, tt_move: Option<ChessMove>) -> Vec<ChessMove> {
     let mut scored_moves: Vec<(ChessMove, i32)> = Vec::new();
     
     for m in moves {
+        // the move the tt remembers (last iteration's best move) always goes first
+        if Some(m) == tt_move {
+            scored_moves.push((m, i32::MAX));
+            continue;
+        }
         let mut move_score_guess = 0;
         let move_piece = board.piece_on(m.get_source()).unwrap();
         let capt_piece = board.piece_on(m.get_dest());
@@ -207,13 +806,25 @@ fn order_moves(moves: MoveGen, board: &Board, killers: &KillerMoves, ply: usize)
         scored_moves.push((m, move_score_guess));
     }
     
-    scored_moves.sort_by(|a, b| b.1.cmp(&a.1));
+    scored_moves.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
     scored_moves.into_iter().map(|(m, _)| m).collect()
 }
 
-fn quiescence_search(board: Board, ai_color: Color, mut alpha: i32, beta: i32, killers: &mut KillerMoves) -> (Option<ChessMove>, i32) {
+fn quiescence_search(board: Board, mut alpha: i32, beta: i32, state: &mut SearchState) -> (Option<ChessMove>, i32) {
+    if state.should_stop() {
+        return (None, 0);
+    }
+    state.nodes += 1;
+    // Check terminal conditions, before standing pat: a mated side can't stand on its eval
+    if board.status() != chess::BoardStatus::Ongoing {
+        if board.status() == chess::BoardStatus::Checkmate {
+            return (None, -(MATE_SCORE - state.ply as i32));
+        }
+        return (None, 0); // Stalemate or other draw
+    }
+
     // Stand-pat score - evaluate current position before looking at captures
-    let stand_pat = eval::evaluate(board, ai_color);
+    let stand_pat = state.static_eval(&board);
     
     // Beta cutoff - position is already too good
     if stand_pat >= beta {
@@ -225,14 +836,6 @@ fn quiescence_search(board: Board, ai_color: Color, mut alpha: i32, beta: i32, k
         alpha = stand_pat;
     }
     
-    // Check terminal conditions
-    if board.status() != chess::BoardStatus::Ongoing {
-        if board.status() == chess::BoardStatus::Checkmate {
-            return (None, if board.side_to_move() == ai_color { -100000 } else { 100000 });
-        }
-        return (None, 0); // Stalemate or other draw
-    }
-    
     let mut best_move = None;
     let mut best_eval = stand_pat; // Initialize with standing pat evaluation
     let mut movegen = MoveGen::new_legal(&board);
@@ -249,11 +852,11 @@ fn quiescence_search(board: Board, ai_color: Color, mut alpha: i32, beta: i32, k
         movegen.set_iterator_mask(*opponent_pieces);
     }
     
-    let moves = order_moves(movegen, &board, killers, 0);
+    let moves = order_moves(movegen, &board, &state.killers, 0, None);
     
     for m in moves {
-        // Skip bad captures using SEE (Static Exchange Evaluation)
-        if !checkers.popcnt() == 0 && board.piece_on(m.get_dest()).is_some() {
+        // Skip bad captures out of check (by piece values, not a real SEE)
+        if checkers.popcnt() == 0 && board.piece_on(m.get_dest()).is_some() {
             let moving_piece = board.piece_on(m.get_source()).unwrap();
             let captured_piece = board.piece_on(m.get_dest()).unwrap();
             
@@ -266,7 +869,13 @@ fn quiescence_search(board: Board, ai_color: Color, mut alpha: i32, beta: i32, k
         let mut new_board = Board::default();
         board.make_move(m, &mut new_board);
         
-        let (_, evaluation) = quiescence_search(new_board, ai_color, -beta, -alpha, killers);
+        state.ply += 1;
+        state.push_position(&board, &new_board);
+        let (_, evaluation) = quiescence_search(new_board, -beta, -alpha, state);
+        state.ply -= 1;
+        if state.stopped {
+            return (None, 0);
+        }
         let negated_eval = -evaluation;
         
         if negated_eval > best_eval {
@@ -283,5 +892,55 @@ fn quiescence_search(board: Board, ai_color: Color, mut alpha: i32, beta: i32, k
         }
     }
     
-    return (best_move, best_eval);
-}
\ No newline at end of file
+    (best_move, best_eval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    // the depth of every iteration reported, and the final result
+    fn iterations(fen: &str, limits: SearchLimits) -> (Vec<u32>, SearchResult) {
+        let board = Board::from_str(fen).unwrap();
+        let mut depths = Vec::new();
+        let result = Engine::new().search(board, &limits, &SearchSignals::default(), |result| depths.push(result.depth));
+        (depths, result)
+    }
+
+    fn to_depth(depth: u32) -> SearchLimits {
+        SearchLimits {
+            depth: Some(depth),
+            ..SearchLimits::default()
+        }
+    }
+
+    #[test]
+    fn proven_mate_ends_the_search() {
+        // back rank mate with Ra8, nothing can be shorter
+        let (depths, result) = iterations("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", to_depth(10));
+        assert_eq!(depths, [1]);
+        assert_eq!(result.mate_in(), Some(1));
+    }
+
+    #[test]
+    fn getting_mated_searches_to_the_depth_limit() {
+        // Kb8 is forced and Rg8 mates
+        let (depths, result) = iterations("k7/8/1K6/8/8/8/8/6R1 b - - 0 1", to_depth(5));
+        assert_eq!(depths, [1, 2, 3, 4, 5]);
+        assert_eq!(result.mate_in(), Some(-1));
+    }
+
+    #[test]
+    fn multi_pv_lines_reach_the_depth_limit_next_to_a_mate() {
+        let limits = SearchLimits {
+            multi_pv: 3,
+            ..to_depth(4)
+        };
+        let (depths, result) = iterations("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", limits);
+        assert_eq!(depths, [1, 2, 3, 4]);
+        assert_eq!(result.lines.len(), 3);
+        assert_eq!(result.lines[0].mate_in(), Some(1));
+        assert!(result.lines[1..].iter().all(|line| line.mate_in().is_none()));
+    }
+}
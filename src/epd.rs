@@ -0,0 +1,327 @@
+use chess::{Board, ChessMove};
+use crate::engine::{Engine, SearchLimits, SearchResult, SearchSignals};
+use std::collections::BTreeMap;
+use std::fs;
+use std::str::FromStr;
+use std::time::Duration;
+
+// epd test suites (wac, sts, ecm, arasan...): one position per line followed by operations,
+// e.g. `<fen> bm Nf3 Nc3; am Qxb7; id "WAC.001"; c0 "Nf3=10, Nc3=5";`
+
+pub struct EpdEntry {
+    pub board: Board,
+    pub id: Option<String>,
+    // bm: any of these solves the position
+    pub best_moves: Vec<ChessMove>,
+    // am: playing any of these fails it
+    pub avoid_moves: Vec<ChessMove>,
+    // c0 to c9, as written
+    pub comments: [Option<String>; 10],
+    // sts scoring: what each listed move is worth, the best move is usually worth 10
+    pub points: Vec<(ChessMove, u32)>,
+}
+
+impl EpdEntry {
+    // whether `m` is an answer to this position. a position with only am solves with anything else
+    pub fn is_solution(&self, m: ChessMove) -> bool {
+        if self.avoid_moves.contains(&m) {
+            return false;
+        }
+        if !self.best_moves.is_empty() {
+            return self.best_moves.contains(&m);
+        }
+        if !self.avoid_moves.is_empty() {
+            return true;
+        }
+        self.points_for(m) > 0
+    }
+
+    pub fn points_for(&self, m: ChessMove) -> u32 {
+        self.points.iter().find(|(scored, _)| *scored == m).map_or(0, |(_, points)| *points)
+    }
+
+    pub fn max_points(&self) -> u32 {
+        self.points.iter().map(|(_, points)| *points).max().unwrap_or(0)
+    }
+
+    // sts ids look like `STS(v1.0) Undermine.001`, the theme is the part in between
+    pub fn theme(&self) -> Option<&str> {
+        let id = self.id.as_deref()?;
+        let name = id.split_once(") ").map_or(id, |(_, name)| name);
+        let theme = name.rsplit_once('.').map_or(name, |(theme, _)| theme);
+        Some(theme.trim())
+    }
+}
+
+pub fn parse_line(line: &str) -> Result<EpdEntry, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 4 {
+        return Err(format!("not an epd line: {}", line));
+    }
+    let board = Board::from_str(&fields[..4].join(" ")).map_err(|_| format!("bad position: {}", fields[..4].join(" ")))?;
+    let mut entry = EpdEntry {
+        board,
+        id: None,
+        best_moves: Vec::new(),
+        avoid_moves: Vec::new(),
+        comments: Default::default(),
+        points: Vec::new(),
+    };
+    // the operations start after the fourth field
+    let operations = line.trim_start();
+    let operations = (0..4).fold(operations, |rest, _| rest.trim_start().split_once(char::is_whitespace).map_or("", |(_, rest)| rest));
+    for operation in split_operations(operations) {
+        let (opcode, operand) = operation.split_once(char::is_whitespace).unwrap_or((operation, ""));
+        let operand = operand.trim();
+        match opcode {
+            "id" => entry.id = Some(unquote(operand).to_string()),
+            "bm" => entry.best_moves = parse_moves(&board, operand)?,
+            "am" => entry.avoid_moves = parse_moves(&board, operand)?,
+            _ if opcode.len() == 2 && opcode.starts_with('c') && opcode.as_bytes()[1].is_ascii_digit() => {
+                let index = (opcode.as_bytes()[1] - b'0') as usize;
+                entry.comments[index] = Some(unquote(operand).to_string());
+            }
+            // hmvc, fmvn, dm, ce... nothing we score on
+            _ => {}
+        }
+    }
+    entry.points = parse_points(&entry)?;
+    Ok(entry)
+}
+
+pub fn load(path: &str) -> Result<Vec<EpdEntry>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(number, line)| parse_line(line).map_err(|e| format!("{} line {}: {}", path, number + 1, e)))
+        .collect()
+}
+
+// operations end in ';', except inside quotes
+pub(crate) fn split_operations(text: &str) -> Vec<&str> {
+    let mut operations = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => {
+                operations.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    operations.push(text[start..].trim());
+    operations.retain(|operation| !operation.is_empty());
+    operations
+}
+
+pub(crate) fn unquote(text: &str) -> &str {
+    text.trim().trim_matches('"')
+}
+
+// epd moves are san, sometimes with check marks and annotations left on
+fn parse_move(board: &Board, text: &str) -> Result<ChessMove, String> {
+    let san = text.trim_end_matches(['+', '#', '!', '?']);
+    ChessMove::from_san(board, san)
+        .or_else(|_| ChessMove::from_str(san))
+        .ok()
+        .filter(|m| board.legal(*m))
+        .ok_or_else(|| format!("bad move: {}", text))
+}
+
+fn parse_moves(board: &Board, text: &str) -> Result<Vec<ChessMove>, String> {
+    text.split_whitespace().map(|m| parse_move(board, m)).collect()
+}
+
+// sts writes its points in c0 as `Nf3=10, Nd2=5`, newer files use c8 (points) and c9
+// (the same moves in uci form)
+fn parse_points(entry: &EpdEntry) -> Result<Vec<(ChessMove, u32)>, String> {
+    if let (Some(points), Some(moves)) = (&entry.comments[8], &entry.comments[9]) {
+        let points = points.split_whitespace().map(|p| p.parse::<u32>().map_err(|_| format!("bad points: {}", p)));
+        let moves = moves.split_whitespace().map(|m| parse_move(&entry.board, m));
+        return moves.zip(points).map(|(m, p)| Ok((m?, p?))).collect();
+    }
+    let Some(c0) = &entry.comments[0] else {
+        return Ok(Vec::new());
+    };
+    if !c0.contains('=') {
+        return Ok(Vec::new());
+    }
+    c0.split(',')
+        .map(|pair| {
+            let (m, points) = pair.split_once('=').ok_or_else(|| format!("bad points: {}", pair))?;
+            let points = points.trim().parse().map_err(|_| format!("bad points: {}", pair))?;
+            Ok((parse_move(&entry.board, m.trim())?, points))
+        })
+        .collect()
+}
+
+// how a single position went
+struct Outcome {
+    found: Option<ChessMove>,
+    solved: bool,
+    points: u32,
+    // when the search settled on a right answer it kept until the end
+    time_to_solution: Option<Duration>,
+}
+
+fn run_position(engine: &Engine, entry: &EpdEntry, limits: &SearchLimits) -> Outcome {
+    engine.new_game();
+    let mut solved_since = None;
+    let on_iteration = |result: &SearchResult| match result.best_move {
+        Some(m) if entry.is_solution(m) => {
+            solved_since.get_or_insert(result.elapsed);
+        }
+        _ => solved_since = None,
+    };
+    let result = engine.search(entry.board, limits, &SearchSignals::default(), on_iteration);
+    let solved = result.best_move.is_some_and(|m| entry.is_solution(m));
+    Outcome {
+        found: result.best_move,
+        solved,
+        points: result.best_move.map_or(0, |m| entry.points_for(m)),
+        time_to_solution: if solved { solved_since } else { None },
+    }
+}
+
+// `epd <file> [movetime <ms>] [depth <n>] [threads <n>] [hash <mb>]`, one second a move by default
+pub fn run(args: &[String]) {
+    let Some(path) = args.first() else {
+        println!("usage: epd <file> [movetime <ms>] [depth <n>] [threads <n>] [hash <mb>]");
+        return;
+    };
+    let entries = match load(path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let mut engine = Engine::new();
+    let mut limits = SearchLimits::default();
+    for pair in args[1..].chunks(2) {
+        let value = pair.get(1).and_then(|v| v.parse::<u64>().ok());
+        match (pair[0].as_str(), value) {
+            ("movetime", Some(millis)) => limits.movetime = Some(Duration::from_millis(millis)),
+            ("depth", Some(depth)) => limits.depth = Some(depth as u32),
+            ("threads", Some(threads)) => engine.set_threads(threads as usize),
+            ("hash", Some(megabytes)) => engine.set_hash_size(megabytes as usize),
+            _ => println!("ignoring {}", pair.join(" ")),
+        }
+    }
+    if limits.movetime.is_none() && limits.depth.is_none() {
+        limits.movetime = Some(Duration::from_secs(1));
+    }
+
+    let mut solved = 0;
+    let mut solve_times = Vec::new();
+    // theme -> (points, max points, positions)
+    let mut themes: BTreeMap<String, (u32, u32, u32)> = BTreeMap::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let outcome = run_position(&engine, entry, &limits);
+        let id = entry.id.clone().unwrap_or_else(|| format!("#{}", i + 1));
+        let found = outcome.found.map_or("(none)".to_string(), |m| m.to_string());
+        let expected = entry.best_moves.iter().map(|m| m.to_string()).collect::<Vec<_>>().join(" ");
+        let avoid = entry.avoid_moves.iter().map(|m| format!("!{}", m)).collect::<Vec<_>>().join(" ");
+        if outcome.solved {
+            solved += 1;
+            let time = outcome.time_to_solution.unwrap_or_default();
+            solve_times.push(time);
+            println!("solved  {:24} {:6} in {} ms", id, found, time.as_millis());
+        } else {
+            println!("failed  {:24} {:6} wanted {}{}", id, found, expected, avoid);
+        }
+        if !entry.points.is_empty() {
+            let theme = entry.theme().unwrap_or("(no theme)").to_string();
+            let score = themes.entry(theme).or_default();
+            score.0 += outcome.points;
+            score.1 += entry.max_points();
+            score.2 += 1;
+        }
+    }
+
+    println!("===========================");
+    println!("solved {}/{}", solved, entries.len());
+    if !solve_times.is_empty() {
+        let total: Duration = solve_times.iter().sum();
+        println!("average time to solution {} ms", (total / solve_times.len() as u32).as_millis());
+    }
+    if !themes.is_empty() {
+        let (mut points, mut max_points) = (0, 0);
+        for (theme, (theme_points, theme_max, positions)) in &themes {
+            println!("{:40} {:5}/{:5} ({} positions)", theme, theme_points, theme_max, positions);
+            points += theme_points;
+            max_points += theme_max;
+        }
+        println!("sts score {}/{}", points, max_points);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -";
+
+    fn uci(text: &str) -> ChessMove {
+        ChessMove::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn quoted_semicolons_stay_in_the_operation() {
+        assert_eq!(
+            split_operations(r#"bm Nf3; c0 "first; second"; id "x.1";"#),
+            vec!["bm Nf3", r#"c0 "first; second""#, r#"id "x.1""#]
+        );
+        // the last operation doesn't need its ';'
+        assert_eq!(split_operations("bm e4; id \"y\""), vec!["bm e4", "id \"y\""]);
+        assert!(split_operations("  ;  ").is_empty());
+
+        let entry = parse_line(&format!(r#"{} bm e4; c0 "a; b"; id "x.1";"#, START)).unwrap();
+        assert_eq!(entry.comments[0].as_deref(), Some("a; b"));
+        assert_eq!(entry.id.as_deref(), Some("x.1"));
+        assert_eq!(entry.best_moves, vec![uci("e2e4")]);
+    }
+
+    #[test]
+    fn best_and_avoid_moves() {
+        let entry = parse_line(&format!("{} bm Nf3 Nc3+ d4!; id \"bm\";", START)).unwrap();
+        assert_eq!(entry.best_moves, vec![uci("g1f3"), uci("b1c3"), uci("d2d4")]);
+        assert!(entry.is_solution(uci("b1c3")));
+        assert!(!entry.is_solution(uci("e2e4")));
+
+        let entry = parse_line(&format!("{} am f3 g4;", START)).unwrap();
+        assert_eq!(entry.avoid_moves, vec![uci("f2f3"), uci("g2g4")]);
+        assert!(entry.best_moves.is_empty());
+        assert!(!entry.is_solution(uci("g2g4")));
+        assert!(entry.is_solution(uci("e2e4")));
+
+        assert!(parse_line(&format!("{} bm Ke2;", START)).is_err());
+        assert!(parse_line("8/8/8 w").is_err());
+    }
+
+    #[test]
+    fn sts_points() {
+        let line = format!(r#"{} bm Nf3; id "STS(v1.0) Undermine.001"; c0 "Nf3=10, Nc3=5, e4=3";"#, START);
+        let entry = parse_line(&line).unwrap();
+        assert_eq!(entry.points, vec![(uci("g1f3"), 10), (uci("b1c3"), 5), (uci("e2e4"), 3)]);
+        assert_eq!(entry.points_for(uci("b1c3")), 5);
+        assert_eq!(entry.points_for(uci("a2a3")), 0);
+        assert_eq!(entry.max_points(), 10);
+        assert_eq!(entry.theme(), Some("Undermine"));
+
+        // the newer layout, points in c8 and the moves in c9
+        let entry = parse_line(&format!(r#"{} c8 "10 4"; c9 "g1f3 d2d4";"#, START)).unwrap();
+        assert_eq!(entry.points, vec![(uci("g1f3"), 10), (uci("d2d4"), 4)]);
+        // no bm, so anything that scores solves it
+        assert!(entry.is_solution(uci("d2d4")));
+        assert!(!entry.is_solution(uci("e2e4")));
+
+        // a plain comment isn't points
+        assert!(parse_line(&format!(r#"{} bm e4; c0 "king's pawn";"#, START)).unwrap().points.is_empty());
+        assert!(parse_line(&format!(r#"{} c0 "Nf3=ten";"#, START)).is_err());
+    }
+}
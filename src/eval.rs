@@ -1,70 +1,19 @@
-use chess::{Board, Color, Piece, ALL_SQUARES, Square};
+use chess::{Board, Color, Piece, ALL_PIECES, ALL_SQUARES};
+use crate::attacks;
+use crate::king_safety::{self, ATTACKER_TERMS, DANGER_SIZE, FILE_TERMS, SHIELD_TERMS};
+use crate::mobility::{self, ACTIVITY_TERMS, BISHOP_MOBILITY, KNIGHT_MOBILITY, QUEEN_MOBILITY, ROOK_MOBILITY};
+use crate::pawns::{self, PawnTable, PASSED_TERMS, PAWN_TERMS};
 use std::cmp;
 
+// the tables are from white's point of view with a1 first, black looks them up mirrored.
+// everything from here to DEFAULT_PARAMS is what the tuner writes out (EvalParams::to_rust).
+// every weight comes as a middlegame (_MID) and an endgame (_END) one, the eval blends the two
+// by how much material is left (see game_phase)
 
-static PIECE_VALUES: &'static [u32] = &[100, 300, 300, 500, 900];
+const PIECE_VALUES_MID: [i32; 5] = [100, 300, 300, 500, 900];
+const PIECE_VALUES_END: [i32; 5] = [120, 290, 310, 520, 920];
 
-static BLACK_PAWN_TABLE: &'static [i32] = &[ 
- 0,  0,  0,  0,  0,  0,  0,  0,
- 50, 50, 50, 50, 50, 50, 50, 50,
- 10, 10, 20, 30, 30, 20, 10, 10,
- 5,  5, 10, 25, 25, 10,  5,  5,
- 0,  0,  0, 20, 20,  0,  0,  0,
- 5, -5,-10,  0,  0,-10, -5,  5,
- 5, 10, 10,-20,-20, 10, 10,  5,
- 0,  0,  0,  0,  0,  0,  0,  0
-];
-static BLACK_KNIGHT_TABLE: &'static [i32] = &[
--50,-40,-30,-30,-30,-30,-40,-50,
--40,-20,  0,  0,  0,  0,-20,-40,
--30,  0, 10, 15, 15, 10,  0,-30,
--30,  5, 15, 20, 20, 15,  5,-30,
--30,  0, 15, 20, 20, 15,  0,-30,
--30,  5, 10, 15, 15, 10,  5,-30,
--40,-20,  0,  5,  5,  0,-20,-40,
--50,-40,-30,-30,-30,-30,-40,-50,
-];
-static BLACK_BISHOP_TABLE: &'static [i32] = &[
-    -20,-10,-10,-10,-10,-10,-10,-20,
-    -10,  0,  0,  0,  0,  0,  0,-10,
-    -10,  0,  5, 10, 10,  5,  0,-10,
-    -10,  5,  5, 10, 10,  5,  5,-10,
-    -10,  0, 10, 10, 10, 10,  0,-10,
-    -10, 10, 10, 10, 10, 10, 10,-10,
-    -10,  5,  0,  0,  0,  0,  5,-10,
-    -20,-10,-10,-10,-10,-10,-10,-20,
-];
-static BLACK_ROOK_TABLE: &'static [i32] = &[
-    0,  0,  0,  0,  0,  0,  0,  0,
-    5, 10, 10, 10, 10, 10, 10,  5,
-   -5,  0,  0,  0,  0,  0,  0, -5,
-   -5,  0,  0,  0,  0,  0,  0, -5,
-   -5,  0,  0,  0,  0,  0,  0, -5,
-   -5,  0,  0,  0,  0,  0,  0, -5,
-   -5,  0,  0,  0,  0,  0,  0, -5,
-    0,  0,  0,  5,  5,  0,  0,  0
-];
-static BLACK_QUEEN_TABLE: &'static [i32] = &[
-    -20,-10,-10, -5, -5,-10,-10,-20,
--10,  0,  0,  0,  0,  0,  0,-10,
--10,  0,  5,  5,  5,  5,  0,-10,
- -5,  0,  5,  5,  5,  5,  0, -5,
-  0,  0,  5,  5,  5,  5,  0, -5,
--10,  5,  5,  5,  5,  5,  0,-10,
--10,  0,  5,  0,  0,  0,  0,-10,
--20,-10,-10, -5, -5,-10,-10,-20
-];
-static BLACK_KING_TABLE_MID: &'static [i32] = &[
--30,-40,-40,-50,-50,-40,-40,-30,
--30,-40,-40,-50,-50,-40,-40,-30,
--30,-40,-40,-50,-50,-40,-40,-30,
--30,-40,-40,-50,-50,-40,-40,-30,
--20,-30,-30,-40,-40,-30,-30,-20,
--10,-20,-20,-20,-20,-20,-20,-10,
- 20, 20,  0,  0,  0,  0, 20, 20,
- 20, 30, 10,  0,  0, 10, 30, 20
-];
-static WHITE_PAWN_TABLE: &'static [i32] = &[
+const PAWN_TABLE_MID: [i32; 64] = [
     0,  0,  0,  0,  0,  0,  0,  0,
     5, 10, 10,-20,-20, 10, 10,  5,
     5, -5,-10,  0,  0,-10, -5,  5,
@@ -75,7 +24,7 @@ static WHITE_PAWN_TABLE: &'static [i32] = &[
     0,  0,  0,  0,  0,  0,  0,  0
 ];
 
-static WHITE_KNIGHT_TABLE: &'static [i32] = &[
+const KNIGHT_TABLE_MID: [i32; 64] = [
    -50,-40,-30,-30,-30,-30,-40,-50,
    -40,-20,  0,  5,  5,  0,-20,-40,
    -30,  5, 10, 15, 15, 10,  5,-30,
@@ -86,7 +35,7 @@ static WHITE_KNIGHT_TABLE: &'static [i32] = &[
    -50,-40,-30,-30,-30,-30,-40,-50
 ];
 
-static WHITE_BISHOP_TABLE: &'static [i32] = &[
+const BISHOP_TABLE_MID: [i32; 64] = [
    -20,-10,-10,-10,-10,-10,-10,-20,
    -10,  5,  0,  0,  0,  0,  5,-10,
    -10, 10, 10, 10, 10, 10, 10,-10,
@@ -97,7 +46,7 @@ static WHITE_BISHOP_TABLE: &'static [i32] = &[
    -20,-10,-10,-10,-10,-10,-10,-20
 ];
 
-static WHITE_ROOK_TABLE: &'static [i32] = &[
+const ROOK_TABLE_MID: [i32; 64] = [
     0,  0,  0,  5,  5,  0,  0,  0,
    -5,  0,  0,  0,  0,  0,  0, -5,
    -5,  0,  0,  0,  0,  0,  0, -5,
@@ -108,7 +57,7 @@ static WHITE_ROOK_TABLE: &'static [i32] = &[
     0,  0,  0,  0,  0,  0,  0,  0
 ];
 
-static WHITE_QUEEN_TABLE: &'static [i32] = &[
+const QUEEN_TABLE_MID: [i32; 64] = [
    -20,-10,-10, -5, -5,-10,-10,-20,
    -10,  0,  5,  0,  0,  0,  0,-10,
    -10,  5,  5,  5,  5,  5,  0,-10,
@@ -119,7 +68,7 @@ static WHITE_QUEEN_TABLE: &'static [i32] = &[
    -20,-10,-10, -5, -5,-10,-10,-20
 ];
 
-static WHITE_KING_TABLE_MID: &'static [i32] = &[
+const KING_TABLE_MID: [i32; 64] = [
     20, 30, 10,  0,  0, 10, 30, 20,
     20, 20,  0,  0,  0,  0, 20, 20,
    -10,-20,-20,-20,-20,-20,-20,-10,
@@ -129,70 +78,499 @@ static WHITE_KING_TABLE_MID: &'static [i32] = &[
    -30,-40,-40,-50,-50,-40,-40,-30,
    -30,-40,-40,-50,-50,-40,-40,-30
 ];
+
+const PAWN_TABLE_END: [i32; 64] = [
+    0,  0,  0,  0,  0,  0,  0,  0,
+   10, 10, 10, 10, 10, 10, 10, 10,
+   10, 10, 10, 10, 10, 10, 10, 10,
+   20, 20, 20, 20, 20, 20, 20, 20,
+   30, 30, 30, 30, 30, 30, 30, 30,
+   50, 50, 50, 50, 50, 50, 50, 50,
+   80, 80, 80, 80, 80, 80, 80, 80,
+    0,  0,  0,  0,  0,  0,  0,  0
+];
+
+// knights and bishops just want the centre once the kings come out, from either side
+const KNIGHT_TABLE_END: [i32; 64] = [
+   -50,-40,-30,-30,-30,-30,-40,-50,
+   -40,-20,  0,  0,  0,  0,-20,-40,
+   -30,  0, 10, 15, 15, 10,  0,-30,
+   -30,  5, 15, 20, 20, 15,  5,-30,
+   -30,  5, 15, 20, 20, 15,  5,-30,
+   -30,  0, 10, 15, 15, 10,  0,-30,
+   -40,-20,  0,  0,  0,  0,-20,-40,
+   -50,-40,-30,-30,-30,-30,-40,-50
+];
+
+const BISHOP_TABLE_END: [i32; 64] = [
+   -20,-10,-10,-10,-10,-10,-10,-20,
+   -10,  0,  0,  0,  0,  0,  0,-10,
+   -10,  0,  5,  5,  5,  5,  0,-10,
+   -10,  0,  5, 10, 10,  5,  0,-10,
+   -10,  0,  5, 10, 10,  5,  0,-10,
+   -10,  0,  5,  5,  5,  5,  0,-10,
+   -10,  0,  0,  0,  0,  0,  0,-10,
+   -20,-10,-10,-10,-10,-10,-10,-20
+];
+
+const ROOK_TABLE_END: [i32; 64] = [
+    0,  0,  0,  0,  0,  0,  0,  0,
+    0,  0,  0,  0,  0,  0,  0,  0,
+    0,  0,  0,  0,  0,  0,  0,  0,
+    0,  0,  0,  0,  0,  0,  0,  0,
+    0,  0,  0,  0,  0,  0,  0,  0,
+    0,  0,  0,  0,  0,  0,  0,  0,
+   10, 10, 10, 10, 10, 10, 10, 10,
+    0,  0,  0,  0,  0,  0,  0,  0
+];
+
+const QUEEN_TABLE_END: [i32; 64] = [
+   -20,-10,-10, -5, -5,-10,-10,-20,
+   -10,  0,  0,  0,  0,  0,  0,-10,
+   -10,  0,  5,  5,  5,  5,  0,-10,
+    -5,  0,  5, 10, 10,  5,  0, -5,
+    -5,  0,  5, 10, 10,  5,  0, -5,
+   -10,  0,  5,  5,  5,  5,  0,-10,
+   -10,  0,  0,  0,  0,  0,  0,-10,
+   -20,-10,-10, -5, -5,-10,-10,-20
+];
+
+// the king belongs in the middle once the queens are gone
+const KING_TABLE_END: [i32; 64] = [
+   -50,-30,-30,-30,-30,-30,-30,-50,
+   -30,-30,  0,  0,  0,  0,-30,-30,
+   -30,-10, 20, 30, 30, 20,-10,-30,
+   -30,-10, 30, 40, 40, 30,-10,-30,
+   -30,-10, 30, 40, 40, 30,-10,-30,
+   -30,-10, 20, 30, 30, 20,-10,-30,
+   -30,-20,-10,  0,  0,-10,-20,-30,
+   -50,-40,-30,-20,-20,-30,-40,-50
+];
+
+// doubled, isolated, backward, connected, phalanx, candidate (see pawns.rs)
+const PAWN_STRUCTURE_MID: [i32; 6] = [-10, -10, -8, 8, 5, 10];
+const PAWN_STRUCTURE_END: [i32; 6] = [-20, -15, -10, 10, 8, 20];
+
+// passed pawns by rank from the pawn's side, then free path, supported path, our king's
+// distance, their king's distance, blockaded and unstoppable (see pawns.rs)
+const PASSED_RANK_MID: [i32; 8] = [0, 5, 5, 10, 20, 35, 55, 0];
+const PASSED_RANK_END: [i32; 8] = [0, 10, 15, 20, 35, 60, 100, 0];
+const PASSED_MID: [i32; 6] = [2, 2, 0, 0, -3, 0];
+const PASSED_END: [i32; 6] = [6, 4, -3, 5, -6, 500];
+
+// king safety, middlegame only (see king_safety.rs). shield pawn one rank up, two ranks up,
+// missing; storm pawn one to four ranks away; open and half open files near the king
+const KING_SHIELD: [i32; 3] = [10, 5, -15];
+const KING_STORM: [i32; 4] = [-5, -15, -10, -5];
+const KING_FILES: [i32; 2] = [-20, -10];
+// attack units per zone square hit and per kind of safe check, knight, bishop, rook, queen
+const KING_ATTACK_WEIGHTS: [i32; 4] = [2, 2, 3, 5];
+const KING_SAFE_CHECKS: [i32; 4] = [6, 4, 7, 8];
+
+// attack units to centipawns
+const KING_DANGER: [i32; 64] = [
+     0,  0,  2,  4,  8, 12, 18, 24,
+    32, 40, 50, 60, 72, 84, 98,112,
+   128,144,162,180,200,220,242,264,
+   288,312,338,364,392,420,450,480,
+   500,500,500,500,500,500,500,500,
+   500,500,500,500,500,500,500,500,
+   500,500,500,500,500,500,500,500,
+   500,500,500,500,500,500,500,500,
+];
+
+// mobility by the number of safe squares a piece can go to (see mobility.rs)
+const KNIGHT_MOBILITY_MID: [i32; 9] = [-30, -20, -10, -3, 3, 8, 13, 17, 20];
+const KNIGHT_MOBILITY_END: [i32; 9] = [-40, -25, -12, -4, 4, 10, 15, 18, 20];
+const BISHOP_MOBILITY_MID: [i32; 14] = [-25, -15, -6, 0, 5, 10, 14, 18, 21, 24, 26, 28, 30, 32];
+const BISHOP_MOBILITY_END: [i32; 14] = [-35, -20, -8, 0, 7, 13, 18, 23, 27, 30, 33, 35, 37, 39];
+const ROOK_MOBILITY_MID: [i32; 15] = [-15, -10, -5, -2, 0, 3, 6, 9, 12, 14, 16, 18, 20, 21, 22];
+const ROOK_MOBILITY_END: [i32; 15] = [-40, -25, -12, -4, 3, 10, 17, 24, 30, 35, 40, 44, 47, 50, 52];
+const QUEEN_MOBILITY_MID: [i32; 28] = [-10, -9, -8, -7, -6, -5, -4, -3, -2, -1, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 15, 15];
+const QUEEN_MOBILITY_END: [i32; 28] = [-20, -18, -16, -14, -12, -10, -8, -6, -4, -2, 0, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 22, 24, 26, 28, 30, 32, 34];
+
+// rook on an open file, on a half open file, on the seventh, knight outpost, bishop pair,
+// bad bishop (per pawn), trapped bishop, trapped rook
+const ACTIVITY_MID: [i32; 8] = [25, 12, 10, 20, 30, -3, -100, -40];
+const ACTIVITY_END: [i32; 8] = [10, 6, 25, 10, 50, -5, -100, -10];
+
+// endgame only: the side that is ahead pushes the other king to the edge and its own king up to it
+const CORNER_EDGE_WEIGHT: i32 = 10;
+const CORNER_KINGS_WEIGHT: i32 = 10;
+
+pub const DEFAULT_PARAMS: EvalParams = EvalParams {
+    piece_values_mid: PIECE_VALUES_MID,
+    piece_values_end: PIECE_VALUES_END,
+    tables_mid: [PAWN_TABLE_MID, KNIGHT_TABLE_MID, BISHOP_TABLE_MID, ROOK_TABLE_MID, QUEEN_TABLE_MID, KING_TABLE_MID],
+    tables_end: [PAWN_TABLE_END, KNIGHT_TABLE_END, BISHOP_TABLE_END, ROOK_TABLE_END, QUEEN_TABLE_END, KING_TABLE_END],
+    pawn_structure_mid: PAWN_STRUCTURE_MID,
+    pawn_structure_end: PAWN_STRUCTURE_END,
+    passed_rank_mid: PASSED_RANK_MID,
+    passed_rank_end: PASSED_RANK_END,
+    passed_mid: PASSED_MID,
+    passed_end: PASSED_END,
+    king_shield: KING_SHIELD,
+    king_storm: KING_STORM,
+    king_files: KING_FILES,
+    king_attack_weights: KING_ATTACK_WEIGHTS,
+    king_safe_checks: KING_SAFE_CHECKS,
+    king_danger: KING_DANGER,
+    knight_mobility_mid: KNIGHT_MOBILITY_MID,
+    knight_mobility_end: KNIGHT_MOBILITY_END,
+    bishop_mobility_mid: BISHOP_MOBILITY_MID,
+    bishop_mobility_end: BISHOP_MOBILITY_END,
+    rook_mobility_mid: ROOK_MOBILITY_MID,
+    rook_mobility_end: ROOK_MOBILITY_END,
+    queen_mobility_mid: QUEEN_MOBILITY_MID,
+    queen_mobility_end: QUEEN_MOBILITY_END,
+    activity_mid: ACTIVITY_MID,
+    activity_end: ACTIVITY_END,
+    corner_edge_weight: CORNER_EDGE_WEIGHT,
+    corner_kings_weight: CORNER_KINGS_WEIGHT,
+};
+
+// every weight of the evaluation in one place, so the tuner can treat them as one vector
+#[derive(Clone)]
+pub struct EvalParams {
+    // pawn to queen
+    pub piece_values_mid: [i32; 5],
+    pub piece_values_end: [i32; 5],
+    // pawn to king, indexed by Piece::to_index()
+    pub tables_mid: [[i32; 64]; 6],
+    pub tables_end: [[i32; 64]; 6],
+    pub pawn_structure_mid: [i32; PAWN_TERMS],
+    pub pawn_structure_end: [i32; PAWN_TERMS],
+    // by rank from the pawn's side, a1 side first for white, a8 for black
+    pub passed_rank_mid: [i32; 8],
+    pub passed_rank_end: [i32; 8],
+    pub passed_mid: [i32; PASSED_TERMS],
+    pub passed_end: [i32; PASSED_TERMS],
+    pub king_shield: [i32; SHIELD_TERMS],
+    pub king_storm: [i32; 4],
+    pub king_files: [i32; FILE_TERMS],
+    pub king_attack_weights: [i32; ATTACKER_TERMS],
+    pub king_safe_checks: [i32; ATTACKER_TERMS],
+    pub king_danger: [i32; DANGER_SIZE],
+    pub knight_mobility_mid: [i32; KNIGHT_MOBILITY],
+    pub knight_mobility_end: [i32; KNIGHT_MOBILITY],
+    pub bishop_mobility_mid: [i32; BISHOP_MOBILITY],
+    pub bishop_mobility_end: [i32; BISHOP_MOBILITY],
+    pub rook_mobility_mid: [i32; ROOK_MOBILITY],
+    pub rook_mobility_end: [i32; ROOK_MOBILITY],
+    pub queen_mobility_mid: [i32; QUEEN_MOBILITY],
+    pub queen_mobility_end: [i32; QUEEN_MOBILITY],
+    pub activity_mid: [i32; ACTIVITY_TERMS],
+    pub activity_end: [i32; ACTIVITY_TERMS],
+    pub corner_edge_weight: i32,
+    pub corner_kings_weight: i32,
+}
+
+// how much each piece counts towards the game phase, pawn to queen. all of them on the board
+// is MAX_PHASE (the opening), none is 0 (a pawn ending)
+const PHASE_WEIGHTS: [i32; 5] = [0, 1, 1, 2, 4];
+pub const MAX_PHASE: i32 = 24;
+// how far ahead in material a side has to be before it goes for the other king
+const MOP_UP_MARGIN: i32 = 200;
+
+impl EvalParams {
+    // all weights in a fixed order, from_vec takes the same order back
+    pub fn to_vec(&self) -> Vec<i32> {
+        let mut v = self.piece_values_mid.to_vec();
+        v.extend_from_slice(&self.piece_values_end);
+        for table in self.tables_mid.iter().chain(&self.tables_end) {
+            v.extend_from_slice(table);
+        }
+        v.extend_from_slice(&self.pawn_structure_mid);
+        v.extend_from_slice(&self.pawn_structure_end);
+        v.extend_from_slice(&self.passed_rank_mid);
+        v.extend_from_slice(&self.passed_rank_end);
+        v.extend_from_slice(&self.passed_mid);
+        v.extend_from_slice(&self.passed_end);
+        v.extend_from_slice(&self.king_shield);
+        v.extend_from_slice(&self.king_storm);
+        v.extend_from_slice(&self.king_files);
+        v.extend_from_slice(&self.king_attack_weights);
+        v.extend_from_slice(&self.king_safe_checks);
+        v.extend_from_slice(&self.king_danger);
+        for table in self.mobility_tables() {
+            v.extend_from_slice(table);
+        }
+        v.extend_from_slice(&self.activity_mid);
+        v.extend_from_slice(&self.activity_end);
+        v.push(self.corner_edge_weight);
+        v.push(self.corner_kings_weight);
+        v
+    }
+
+    pub fn from_vec(v: &[i32]) -> Self {
+        let mut params = DEFAULT_PARAMS;
+        let mut values = v.iter().copied();
+        let weights = params
+            .piece_values_mid
+            .iter_mut()
+            .chain(params.piece_values_end.iter_mut())
+            .chain(params.tables_mid.iter_mut().flatten())
+            .chain(params.tables_end.iter_mut().flatten())
+            .chain(params.pawn_structure_mid.iter_mut())
+            .chain(params.pawn_structure_end.iter_mut())
+            .chain(params.passed_rank_mid.iter_mut())
+            .chain(params.passed_rank_end.iter_mut())
+            .chain(params.passed_mid.iter_mut())
+            .chain(params.passed_end.iter_mut())
+            .chain(params.king_shield.iter_mut())
+            .chain(params.king_storm.iter_mut())
+            .chain(params.king_files.iter_mut())
+            .chain(params.king_attack_weights.iter_mut())
+            .chain(params.king_safe_checks.iter_mut())
+            .chain(params.king_danger.iter_mut())
+            .chain(params.knight_mobility_mid.iter_mut())
+            .chain(params.knight_mobility_end.iter_mut())
+            .chain(params.bishop_mobility_mid.iter_mut())
+            .chain(params.bishop_mobility_end.iter_mut())
+            .chain(params.rook_mobility_mid.iter_mut())
+            .chain(params.rook_mobility_end.iter_mut())
+            .chain(params.queen_mobility_mid.iter_mut())
+            .chain(params.queen_mobility_end.iter_mut())
+            .chain(params.activity_mid.iter_mut())
+            .chain(params.activity_end.iter_mut())
+            .chain([&mut params.corner_edge_weight, &mut params.corner_kings_weight]);
+        for weight in weights {
+            *weight = values.next().expect("parameter vector too short");
+        }
+        params
+    }
+
+    // knight, bishop, rook and queen, middlegame then endgame for each
+    fn mobility_tables(&self) -> [&[i32]; 8] {
+        [
+            &self.knight_mobility_mid,
+            &self.knight_mobility_end,
+            &self.bishop_mobility_mid,
+            &self.bishop_mobility_end,
+            &self.rook_mobility_mid,
+            &self.rook_mobility_end,
+            &self.queen_mobility_mid,
+            &self.queen_mobility_end,
+        ]
+    }
+
+    // the constants at the top of this file with these values, ready to paste over them
+    pub fn to_rust(&self) -> String {
+        let mut out = String::new();
+        out.push_str(&format!("const PIECE_VALUES_MID: [i32; 5] = {:?};\n", self.piece_values_mid));
+        out.push_str(&format!("const PIECE_VALUES_END: [i32; 5] = {:?};\n", self.piece_values_end));
+        let names = ["PAWN", "KNIGHT", "BISHOP", "ROOK", "QUEEN", "KING"];
+        for (phase, tables) in [("MID", &self.tables_mid), ("END", &self.tables_end)] {
+            for (name, table) in names.iter().zip(tables) {
+                out.push_str(&format!("\nconst {}_TABLE_{}: [i32; 64] = [\n", name, phase));
+                for rank in table.chunks(8) {
+                    let row: Vec<String> = rank.iter().map(|v| format!("{:3}", v)).collect();
+                    out.push_str(&format!("   {},\n", row.join(",")));
+                }
+                out.push_str("];\n");
+            }
+        }
+        out.push_str(&format!("\nconst PAWN_STRUCTURE_MID: [i32; 6] = {:?};\n", self.pawn_structure_mid));
+        out.push_str(&format!("const PAWN_STRUCTURE_END: [i32; 6] = {:?};\n", self.pawn_structure_end));
+        out.push_str(&format!("\nconst PASSED_RANK_MID: [i32; 8] = {:?};\n", self.passed_rank_mid));
+        out.push_str(&format!("const PASSED_RANK_END: [i32; 8] = {:?};\n", self.passed_rank_end));
+        out.push_str(&format!("const PASSED_MID: [i32; 6] = {:?};\n", self.passed_mid));
+        out.push_str(&format!("const PASSED_END: [i32; 6] = {:?};\n", self.passed_end));
+        out.push_str(&format!("\nconst KING_SHIELD: [i32; 3] = {:?};\n", self.king_shield));
+        out.push_str(&format!("const KING_STORM: [i32; 4] = {:?};\n", self.king_storm));
+        out.push_str(&format!("const KING_FILES: [i32; 2] = {:?};\n", self.king_files));
+        out.push_str(&format!("const KING_ATTACK_WEIGHTS: [i32; 4] = {:?};\n", self.king_attack_weights));
+        out.push_str(&format!("const KING_SAFE_CHECKS: [i32; 4] = {:?};\n", self.king_safe_checks));
+        out.push_str("\nconst KING_DANGER: [i32; 64] = [\n");
+        for row in self.king_danger.chunks(8) {
+            let row: Vec<String> = row.iter().map(|v| format!("{:3}", v)).collect();
+            out.push_str(&format!("   {},\n", row.join(",")));
+        }
+        out.push_str("];\n");
+        out.push('\n');
+        let names = ["KNIGHT", "BISHOP", "ROOK", "QUEEN"];
+        for (index, table) in self.mobility_tables().iter().enumerate() {
+            let phase = if index % 2 == 0 { "MID" } else { "END" };
+            out.push_str(&format!("const {}_MOBILITY_{}: [i32; {}] = {:?};\n", names[index / 2], phase, table.len(), table));
+        }
+        out.push_str(&format!("\nconst ACTIVITY_MID: [i32; 8] = {:?};\n", self.activity_mid));
+        out.push_str(&format!("const ACTIVITY_END: [i32; 8] = {:?};\n", self.activity_end));
+        out.push_str(&format!("\nconst CORNER_EDGE_WEIGHT: i32 = {};\n", self.corner_edge_weight));
+        out.push_str(&format!("const CORNER_KINGS_WEIGHT: i32 = {};\n", self.corner_kings_weight));
+        out
+    }
+}
+
+// MAX_PHASE with all pieces on the board down to 0 without any, pawns don't count
+pub fn game_phase(board: &Board) -> i32 {
+    let phase: i32 = ALL_PIECES[..5]
+        .iter()
+        .map(|piece| board.pieces(*piece).popcnt() as i32 * PHASE_WEIGHTS[piece.to_index()])
+        .sum();
+    // promotions can take it past the start
+    phase.min(MAX_PHASE)
+}
+
+// blends a middlegame and an endgame score by the phase
+pub fn taper(mid: i32, end: i32, phase: i32) -> i32 {
+    (mid * phase + end * (MAX_PHASE - phase)) / MAX_PHASE
+}
+
+// (middlegame, endgame) for white and for black, each side's own score before they're taken
+// away from each other
+pub type Sides = [(i32, i32); 2];
+
+// one row of an EvalTrace
+pub struct TraceTerm {
+    pub name: &'static str,
+    pub sides: Sides,
+}
+
+// every term of the evaluation of a position split by colour and phase, for the `eval` command
+pub struct EvalTrace {
+    pub terms: Vec<TraceTerm>,
+    pub phase: i32,
+    // the tapered score from white's point of view, the same as evaluate(board, Color::White)
+    pub score: i32,
+    pub stalemate: bool,
+}
+
+impl EvalTrace {
+    // white minus black over every term, (middlegame, endgame)
+    pub fn total(&self) -> (i32, i32) {
+        self.terms.iter().fold((0, 0), |(mid, end), term| {
+            (mid + term.sides[0].0 - term.sides[1].0, end + term.sides[0].1 - term.sides[1].1)
+        })
+    }
+}
+
+impl std::fmt::Display for EvalTrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.stalemate {
+            return writeln!(f, "stalemate, the score is 0");
+        }
+        let line = "-----------------+---------------+---------------+---------------";
+        let header = format!("{:>16} | {:^13} | {:^13} | {:^13}", "term", "white", "black", "total");
+        writeln!(f, "{}", header.trim_end())?;
+        writeln!(f, "{:>16} | {:>6} {:>6} | {:>6} {:>6} | {:>6} {:>6}", "", "mg", "eg", "mg", "eg", "mg", "eg")?;
+        writeln!(f, "{}", line)?;
+        for term in &self.terms {
+            let [(white_mid, white_end), (black_mid, black_end)] = term.sides;
+            writeln!(
+                f,
+                "{:>16} | {:>6} {:>6} | {:>6} {:>6} | {:>6} {:>6}",
+                term.name, white_mid, white_end, black_mid, black_end, white_mid - black_mid, white_end - black_end
+            )?;
+        }
+        writeln!(f, "{}", line)?;
+        let (mid, end) = self.total();
+        writeln!(f, "{:>16} | {:>13} | {:>13} | {:>6} {:>6}", "total", "", "", mid, end)?;
+        writeln!(f)?;
+        writeln!(f, "phase {} of {} (mg weight {}%)", self.phase, MAX_PHASE, self.phase * 100 / MAX_PHASE)?;
+        writeln!(f, "final evaluation {} (white's point of view)", self.score)
+    }
+}
+
+// the score is for `color`: positive when `color` is better, and evaluate(board, !color) is
+// always -evaluate(board, color). the same position with the colours swapped (board flipped,
+// side to move too) gets the same score for the other side
 pub fn evaluate(board: Board, color: Color) -> i32 {
+    evaluate_with(board, color, &DEFAULT_PARAMS)
+}
+
+pub fn evaluate_with(board: Board, color: Color, params: &EvalParams) -> i32 {
+    evaluate_inner(board, color, params, None, None)
+}
+
+// what the search calls: default weights, pawn structure out of the pawn table when it's there,
+// and the score for the side to move like everything else in a negamax search
+pub fn evaluate_cached(board: Board, pawn_table: &mut PawnTable) -> i32 {
+    evaluate_inner(board, board.side_to_move(), &DEFAULT_PARAMS, Some(pawn_table), None)
+}
+
+// evaluate() with every term written down
+pub fn trace(board: Board) -> EvalTrace {
+    trace_with(board, &DEFAULT_PARAMS)
+}
+
+pub fn trace_with(board: Board, params: &EvalParams) -> EvalTrace {
+    let mut trace = EvalTrace {
+        terms: Vec::new(),
+        phase: game_phase(&board),
+        score: 0,
+        stalemate: board.status() == chess::BoardStatus::Stalemate,
+    };
+    trace.score = evaluate_inner(board, Color::White, params, None, Some(&mut trace));
+    trace
+}
+
+fn evaluate_inner(board: Board, color: Color, params: &EvalParams, pawn_table: Option<&mut PawnTable>, mut trace: Option<&mut EvalTrace>) -> i32 {
     
     // let opposite_color = if color == Color::White { Color::Black } else { Color::White };
     // println!("{:?}", new_board.status());
     if board.status() == chess::BoardStatus::Stalemate {
         return 0;
     }
-    // piece bitboards (required for eval anyway, but also work for piece counting)
-    let white_piece_bitboard = board.color_combined(Color::White);
-    let black_piece_bitboard = board.color_combined(Color::Black);
-    // opposite color piece count
-    let opposite_piece_count: i32 = if color == Color::White {
-        black_piece_bitboard.popcnt() as i32
-    } else {
-        white_piece_bitboard.popcnt() as i32
+    // every term is worked out for both sides, then added up white minus black, middlegame and
+    // endgame separately
+    let (mut mid, mut end) = (0, 0);
+    let mut add = |name: &'static str, sides: Sides| {
+        mid += sides[0].0 - sides[1].0;
+        end += sides[0].1 - sides[1].1;
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.terms.push(TraceTerm { name, sides });
+        }
     };
+    let material = material(&board, params);
+    add("material", material);
+    let tables = piece_square_table_eval(&board, params);
+    for (name, sides) in ["pawn pst", "knight pst", "bishop pst", "rook pst", "queen pst", "king pst"].into_iter().zip(tables) {
+        add(name, sides);
+    }
+    let pawn_score = match pawn_table {
+        Some(pawn_table) => pawn_table.probe(&board, params),
+        None => pawns::evaluate(&board, params),
+    };
+    add("pawn structure", pawn_score.structure);
+    let attacked = [attacks::attacks(&board, Color::White), attacks::attacks(&board, Color::Black)];
+    add("passed pawns", pawns::evaluate_passed(&board, pawn_score.passed, &attacked, params));
+    add("king safety", king_safety::evaluate(&board, &attacked, params));
+    let (mobility, activity) = mobility::evaluate(&board, params);
+    add("mobility", mobility);
+    add("piece activity", activity);
 
-    let mut score: i32 = 0;
-    let pawn_bitboard = board.pieces(Piece::Pawn);
-    let knight_bitboard = board.pieces(Piece::Knight);
-    let bishop_bitboard = board.pieces(Piece::Bishop);
-    let rook_bitboard = board.pieces(Piece::Rook);
-    let queen_bitboard = board.pieces(Piece::Queen);
-    
-    
-    let w_pawn_bitboard = pawn_bitboard & white_piece_bitboard;
-    let b_pawn_bitboard = pawn_bitboard & black_piece_bitboard;
-
-    let w_knight_bitboard = knight_bitboard & white_piece_bitboard;
-    let b_knight_bitboard = knight_bitboard & black_piece_bitboard;
-
-    let w_bishop_bitboard = bishop_bitboard & white_piece_bitboard;
-    let b_bishop_bitboard = bishop_bitboard & black_piece_bitboard;
-
-    let w_rook_bitboard = rook_bitboard & white_piece_bitboard;
-    let b_rook_bitboard = rook_bitboard & black_piece_bitboard;
-
-    let w_queen_bitboard = queen_bitboard & white_piece_bitboard;
-    let b_queen_bitboard = queen_bitboard & black_piece_bitboard;
-
-    score += (w_pawn_bitboard.popcnt() as i32) * PIECE_VALUES[0] as i32;
-    score += (w_knight_bitboard.popcnt() as i32) * PIECE_VALUES[1] as i32;
-    score += (w_bishop_bitboard.popcnt() as i32) * PIECE_VALUES[2] as i32;
-    score += (w_rook_bitboard.popcnt() as i32) * PIECE_VALUES[3] as i32;
-    score += (w_queen_bitboard.popcnt() as i32) * PIECE_VALUES[4] as i32;
-    
-    score -= (b_pawn_bitboard.popcnt() as i32) * PIECE_VALUES[0] as i32;
-    score -= (b_knight_bitboard.popcnt() as i32) * PIECE_VALUES[1] as i32;
-    score -= (b_bishop_bitboard.popcnt() as i32) * PIECE_VALUES[2] as i32;
-    score -= (b_rook_bitboard.popcnt() as i32) * PIECE_VALUES[3] as i32;
-    score -= (b_queen_bitboard.popcnt() as i32) * PIECE_VALUES[4] as i32;
-    
-    if opposite_piece_count <= 7 {
-        score -= force_king_to_corner(board, if color == Color::White { Color::Black } else { Color::White }, opposite_piece_count);
+    // the side that's ahead gets the bonus for driving the other king to the edge
+    let material_end = material[0].1 - material[1].1;
+    let mut corner = Sides::default();
+    if material_end >= MOP_UP_MARGIN {
+        corner[0].1 = force_king_to_corner(board, Color::Black, params);
+    } else if material_end <= -MOP_UP_MARGIN {
+        corner[1].1 = force_king_to_corner(board, Color::White, params);
     }
-    score += piece_square_table_eval(&board, opposite_piece_count);
+    add("king corner", corner);
+
+    let score = taper(mid, end, game_phase(&board));
     let perspective: i32 = if color == Color::White { 1 } else { -1 };
-    score *= perspective;
     // println!("Score: {}", score);
-    return score;
+    score * perspective
+}
+
+// piece values
+fn material(board: &Board, params: &EvalParams) -> Sides {
+    let mut sides = Sides::default();
+    for color in [Color::White, Color::Black] {
+        let (mid, end) = &mut sides[color.to_index()];
+        for piece in &ALL_PIECES[..5] {
+            let count = (board.pieces(*piece) & board.color_combined(color)).popcnt() as i32;
+            *mid += count * params.piece_values_mid[piece.to_index()];
+            *end += count * params.piece_values_end[piece.to_index()];
+        }
+    }
+    sides
 }
 
-fn force_king_to_corner(board: Board, color: Color, piece_count: i32) -> i32 {
-    let mut eval = 0;
+// mop-up: how far `color`'s king is from the centre and how close the other king is to it
+fn force_king_to_corner(board: Board, color: Color, params: &EvalParams) -> i32 {
     let opp_king_square = board.king_square(color);
     let opp_king_x = opp_king_square.get_file() as i32;
     let opp_king_y = opp_king_square.get_rank() as i32;
@@ -201,93 +579,69 @@ fn force_king_to_corner(board: Board, color: Color, piece_count: i32) -> i32 {
     let opp_king_dist_to_centre_rank = cmp::max(3 - opp_king_y, opp_king_y - 4);
     let opp_king_dist_to_centre = opp_king_dist_to_centre_file + opp_king_dist_to_centre_rank;
 
-    eval += opp_king_dist_to_centre;
-
     // move king to cut off stuff
-    let king_square = board.king_square(if color == Color::White { Color::Black } else { Color::White });
+    let king_square = board.king_square(!color);
     
     let my_king_x = king_square.get_file() as i32;
     let my_king_y = king_square.get_rank() as i32;
 
     let dist_between_kings = i32::abs(my_king_x - opp_king_x) + i32::abs(my_king_y - opp_king_y);
-    eval += 14 - dist_between_kings;
-    let pieces_taken = 20 - piece_count;
-
-    return eval * 10 * pieces_taken;
+    opp_king_dist_to_centre * params.corner_edge_weight + (14 - dist_between_kings) * params.corner_kings_weight
 }
 // this is for move ordering, not used in evaluation
 pub fn get_piece_value(piece: Piece) -> i32 {
     match piece {
-        Piece::Pawn => PIECE_VALUES[0] as i32,
-        Piece::Knight => PIECE_VALUES[1] as i32,
-        Piece::Bishop => PIECE_VALUES[2] as i32,
-        Piece::Rook => PIECE_VALUES[3] as i32,
-        Piece::Queen => PIECE_VALUES[4] as i32,
         Piece::King => 0, // King value is not used in evaluation
+        _ => PIECE_VALUES_MID[piece.to_index()],
     }
 }
 
-fn piece_square_table_eval(board: &Board, opposite_piece_count: i32) -> i32 {
-    let mut eval = 0;
+// one per piece, pawn to king
+fn piece_square_table_eval(board: &Board, params: &EvalParams) -> [Sides; 6] {
+    let mut tables = [Sides::default(); 6];
     for square in ALL_SQUARES.iter() {
-        let piece_opt = board.piece_on(*square);
-        if piece_opt.is_some()
+        if let Some(piece) = board.piece_on(*square)
         {
-            let piece = piece_opt.unwrap();
-            if board.color_on(*square).unwrap() == Color::White {
-                match piece
-                {
-                    Piece::Pawn => {
-                        eval += WHITE_PAWN_TABLE[square.to_index() as usize] as i32;
-                    }
-                    Piece::Knight => {
-                        eval += WHITE_KNIGHT_TABLE[square.to_index() as usize] as i32;
-                    }
-                    Piece::Bishop => {
-                        eval += WHITE_BISHOP_TABLE[square.to_index() as usize] as i32;
-                    }
-                    Piece::Rook => {
-                        eval += WHITE_ROOK_TABLE[square.to_index() as usize] as i32;
-                    }
-                    Piece::Queen => {
-                        eval += WHITE_QUEEN_TABLE[square.to_index() as usize] as i32;
-                    }
-                    Piece::King => {
-                        if opposite_piece_count >= 7 {
-                            eval += WHITE_KING_TABLE_MID[square.to_index() as usize] as i32;
-                        }
-                    }
-                    _ => {}
-                }
-            }
-            else
-            {
-                match piece
-                {
-                    Piece::Pawn => {
-                        eval -= BLACK_PAWN_TABLE[square.to_index() as usize] as i32;
-                    }
-                    Piece::Knight => {
-                        eval -= BLACK_KNIGHT_TABLE[square.to_index() as usize] as i32;
-                    }
-                    Piece::Bishop => {
-                        eval -= BLACK_BISHOP_TABLE[square.to_index() as usize] as i32;
-                    }
-                    Piece::Rook => {
-                        eval -= BLACK_ROOK_TABLE[square.to_index() as usize] as i32;
-                    }
-                    Piece::Queen => {
-                        eval -= BLACK_QUEEN_TABLE[square.to_index() as usize] as i32;
-                    }
-                    Piece::King => {
-                        if opposite_piece_count >= 7 {
-                            eval -= BLACK_KING_TABLE_MID[square.to_index() as usize] as i32;
-                        }
-                    }
-                    _ => {}
-                }
-            }
+            let (table_mid, table_end) = (&params.tables_mid[piece.to_index()], &params.tables_end[piece.to_index()]);
+            let color = board.color_on(*square).unwrap();
+            // the tables are drawn for white, black reads them upside down
+            let index = if color == Color::White { square.to_index() } else { square.to_index() ^ 56 };
+            let (mid, end) = &mut tables[piece.to_index()][color.to_index()];
+            *mid += table_mid[index];
+            *end += table_end[index];
         }
     }  
-    return eval; 
-}
\ No newline at end of file
+    tables
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chess::Square;
+    use std::str::FromStr;
+
+    // endgame table score of the one `piece` of `color` on `square`, the kings tucked away
+    fn endgame_score(piece: Piece, color: Color, square: Square) -> i32 {
+        let mut builder = chess::BoardBuilder::new();
+        builder.piece(Square::H1, Piece::King, Color::White).piece(Square::H8, Piece::King, Color::Black).piece(square, piece, color);
+        // the piece may be giving check
+        builder.side_to_move(!color);
+        let board = Board::try_from(&builder).unwrap();
+        piece_square_table_eval(&board, &DEFAULT_PARAMS)[piece.to_index()][color.to_index()].1
+    }
+
+    // the endgame tables are drawn the same way up as the middlegame ones, and for knights and
+    // bishops being central is all that counts, so a square scores the same for white and black
+    #[test]
+    fn minor_piece_endgame_tables() {
+        for piece in [Piece::Knight, Piece::Bishop] {
+            for square in ALL_SQUARES.iter().filter(|square| ![Square::H1, Square::H8].contains(square)) {
+                assert_eq!(endgame_score(piece, Color::White, *square), endgame_score(piece, Color::Black, *square), "{:?} on {}", piece, square);
+            }
+        }
+        let bishop = |square| endgame_score(Piece::Bishop, Color::White, Square::from_str(square).unwrap());
+        assert_eq!([bishop("c3"), bishop("c6"), bishop("d4"), bishop("a1")], [5, 5, 10, -20]);
+        let knight = |square| endgame_score(Piece::Knight, Color::Black, Square::from_str(square).unwrap());
+        assert_eq!([knight("d4"), knight("e5"), knight("b2"), knight("h4")], [20, 20, -20, -30]);
+    }
+}
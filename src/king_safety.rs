@@ -0,0 +1,184 @@
+use chess::{get_bishop_moves, get_king_moves, get_knight_moves, get_rook_moves, BitBoard, Board, Color, Piece, Square};
+use crate::eval::{EvalParams, Sides};
+use crate::pawns::{file_mask, ranks_ahead};
+
+// king safety, a middlegame term (kings are meant to come out in the endgame). made of:
+//
+// - the pawn shield: our pawns right in front of the king and on the files next to it
+// - pawn storms: their pawns coming up those files
+// - open and half open files at the king
+// - attacks on the king zone (the king and the squares around it): every piece of theirs that
+//   hits the zone adds its weight per square it hits to the attack units, and so does every
+//   kind of piece that has a check on a square we don't cover. once two or more pieces join in
+//   the units are looked up in the danger table, which grows faster than the units do
+//
+// all weights are in EvalParams, the danger table is indexed by attack units
+
+// fewer attackers than this are no danger on their own
+const MIN_ATTACKERS: u32 = 2;
+// how far up the file pawn storms count, in ranks from the king
+const STORM_RANKS: usize = 4;
+
+// shield: a pawn one rank up, two ranks up, none (or further away)
+pub const SHIELD_TERMS: usize = 3;
+// open file (no pawns at all), half open (none of ours)
+pub const FILE_TERMS: usize = 2;
+// knight, bishop, rook, queen, for attack weights and safe checks
+pub const ATTACKER_TERMS: usize = 4;
+pub const DANGER_SIZE: usize = 64;
+
+// middlegame only, the endgame half is always 0. `attacked` is every square each side attacks,
+// white first
+pub fn evaluate(board: &Board, attacked: &[u64; 2], params: &EvalParams) -> Sides {
+    [(king_safety(board, Color::White, attacked, params), 0), (king_safety(board, Color::Black, attacked, params), 0)]
+}
+
+// how safe `color`'s king is, less is worse
+fn king_safety(board: &Board, color: Color, attacked: &[u64; 2], params: &EvalParams) -> i32 {
+    let king = board.king_square(color);
+    pawn_cover(board, color, king, params) - king_danger(board, color, king, attacked, params)
+}
+
+// shield, storm and files on the king's file and its neighbours
+fn pawn_cover(board: &Board, color: Color, king: Square, params: &EvalParams) -> i32 {
+    let pawns = board.pieces(Piece::Pawn).0;
+    let ours = pawns & board.color_combined(color).0;
+    let theirs = pawns & !ours;
+    let ahead = ranks_ahead(color, king);
+    let king_rank = king.get_rank().to_index();
+    // a king on the edge is covered by the same three files as one next to it
+    let centre = king.get_file().to_index().clamp(1, 6);
+    let mut score = 0;
+    for file in centre - 1..=centre + 1 {
+        let file_bits = file_mask(file);
+        let shield = match nearest(color, ours & file_bits & ahead).map(|rank| rank.abs_diff(king_rank)) {
+            Some(1) => 0,
+            Some(2) => 1,
+            _ => 2,
+        };
+        score += params.king_shield[shield];
+        if let Some(rank) = nearest(color, theirs & file_bits & ahead) {
+            let distance = rank.abs_diff(king_rank);
+            if distance <= STORM_RANKS {
+                score += params.king_storm[distance - 1];
+            }
+        }
+        if pawns & file_bits == 0 {
+            score += params.king_files[0];
+        } else if ours & file_bits == 0 {
+            score += params.king_files[1];
+        }
+    }
+    score
+}
+
+// rank of the pawn in `pawns` closest to `color`'s side of the board
+fn nearest(color: Color, pawns: u64) -> Option<usize> {
+    if pawns == 0 {
+        return None;
+    }
+    let square = if color == Color::White { pawns.trailing_zeros() } else { 63 - pawns.leading_zeros() };
+    Some(square as usize / 8)
+}
+
+// attack units on the zone around `color`'s king through the danger table
+fn king_danger(board: &Board, color: Color, king: Square, attacked: &[u64; 2], params: &EvalParams) -> i32 {
+    let zone = get_king_moves(king).0 | (1 << king.to_index());
+    let occupied = *board.combined();
+    let theirs = *board.color_combined(!color);
+    // squares they could check from without losing the piece straight away
+    let safe = !attacked[color.to_index()] & !theirs.0;
+    let knight_checks = get_knight_moves(king).0 & safe;
+    let bishop_checks = get_bishop_moves(king, occupied).0 & safe;
+    let rook_checks = get_rook_moves(king, occupied).0 & safe;
+
+    let mut attackers = 0;
+    let mut units = 0;
+    // the squares each kind of piece of theirs reaches, for the safe checks
+    let mut reach = [0u64; ATTACKER_TERMS];
+    for (index, piece) in [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen].into_iter().enumerate() {
+        for square in board.pieces(piece) & theirs {
+            let moves = match piece {
+                Piece::Knight => get_knight_moves(square),
+                Piece::Bishop => get_bishop_moves(square, occupied),
+                Piece::Rook => get_rook_moves(square, occupied),
+                _ => get_bishop_moves(square, occupied) | get_rook_moves(square, occupied),
+            };
+            reach[index] |= moves.0;
+            let hits = moves & BitBoard(zone);
+            if hits.0 != 0 {
+                attackers += 1;
+                units += params.king_attack_weights[index] * hits.popcnt() as i32;
+            }
+        }
+    }
+    let checks = [knight_checks, bishop_checks, rook_checks, bishop_checks | rook_checks];
+    for (index, (reach, checks)) in reach.iter().zip(checks).enumerate() {
+        if reach & checks != 0 {
+            units += params.king_safe_checks[index];
+        }
+    }
+    if attackers < MIN_ATTACKERS {
+        return 0;
+    }
+    params.king_danger[units.clamp(0, DANGER_SIZE as i32 - 1) as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attacks::attacks;
+    use crate::eval::DEFAULT_PARAMS;
+    use std::str::FromStr;
+
+    // shield one up / two up / missing, storm one to four ranks away, open / half open file
+    fn params() -> EvalParams {
+        let mut params = EvalParams::from_vec(&vec![0; DEFAULT_PARAMS.to_vec().len()]);
+        params.king_shield = [0, -10, -30];
+        params.king_storm = [-40, -30, -20, -10];
+        params.king_files = [-50, -20];
+        params
+    }
+
+    fn safety(fen: &str, params: &EvalParams) -> [i32; 2] {
+        let board = Board::from_str(fen).unwrap();
+        let attacked = [attacks(&board, Color::White), attacks(&board, Color::Black)];
+        let sides = evaluate(&board, &attacked, params);
+        assert_eq!((sides[0].1, sides[1].1), (0, 0));
+        [sides[0].0, sides[1].0]
+    }
+
+    #[test]
+    fn pawn_shield_and_files() {
+        let params = params();
+        // both castled short behind three pawns
+        assert_eq!(safety("6k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1", &params), [0, 0]);
+        // g3 is one rank further up
+        assert_eq!(safety("6k1/5ppp/8/8/8/6P1/5P1P/6K1 w - - 0 1", &params), [-10, 0]);
+        // no g-pawns at all: no shield and an open file, for whichever side it is
+        assert_eq!(safety("6k1/5p1p/8/8/8/8/5P1P/6K1 w - - 0 1", &params), [-80, -80]);
+        // black's g-pawn came down to g4: a half open file and a storm three ranks away for
+        // white, and just no shield for black
+        assert_eq!(safety("6k1/5p1p/8/8/6p1/8/5P1P/6K1 w - - 0 1", &params), [-30 - 20 - 20, -30]);
+        // a king on the edge looks at the same files as one next to it
+        assert_eq!(safety("7k/5ppp/8/8/8/8/5PPP/7K w - - 0 1", &params), [0, 0]);
+    }
+
+    #[test]
+    fn king_zone_attacks() {
+        let mut params = params();
+        params.king_attack_weights = [1, 1, 1, 1];
+        params.king_danger = std::array::from_fn(|units| units as i32);
+        // the rook hits f2 and the queen f2 and h2: two attackers, three units
+        assert_eq!(safety("5rk1/6pp/8/8/7q/8/5PPP/6K1 w - - 0 1", &params)[0], -3);
+        // right next to the king the rook hits f2 and g1
+        assert_eq!(safety("6k1/6pp/8/8/7q/8/5PPP/5rK1 w - - 0 1", &params)[0], -4);
+        // the queen on her own is no danger
+        assert_eq!(safety("6k1/6pp/8/8/7q/8/5PPP/6K1 w - - 0 1", &params)[0], 0);
+        // a check the queen could give on a square we don't cover counts on top. from h4 she
+        // has none, from e4 she hits g2 and can check on e1
+        params.king_safe_checks = [0, 0, 0, 5];
+        assert_eq!(safety("5rk1/6pp/8/8/7q/8/5PPP/6K1 w - - 0 1", &params)[0], -3);
+        assert_eq!(safety("5rk1/6pp/8/8/4q3/8/5PPP/6K1 w - - 0 1", &params)[0], -(1 + 1 + 5));
+    }
+}
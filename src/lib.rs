@@ -0,0 +1,20 @@
+pub mod attacks;
+pub mod bench;
+pub mod engine;
+pub mod epd;
+pub mod eval;
+pub mod king_safety;
+pub mod match_runner;
+pub mod mobility;
+pub mod nnue;
+pub mod pawns;
+pub mod perft;
+pub mod pgn;
+pub mod rng;
+pub mod selfplay;
+pub mod sprt;
+pub mod timeman;
+pub mod tt;
+pub mod tuner;
+pub mod uci;
+pub mod xboard;
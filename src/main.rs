@@ -1,16 +1,47 @@
-use chess::{ChessMove, Game, Square, Color, Piece};
+use chess::{BoardStatus, ChessMove, Game, Color};
+use chessengine_plsbuild::engine::{self, Engine, SearchHandle, SearchLimits, SearchSignals};
+use chessengine_plsbuild::{bench, epd, eval, match_runner, perft, selfplay, tuner, uci, xboard};
 use std::io;
+use std::str::FromStr;
 use std::time::Instant;
-mod engine;
-mod eval;
 
 fn main() {
+    // protocol modes and tools can be picked on the command line (`chessengine-plsbuild uci`)
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(mode) = args.first() {
+        match mode.as_str() {
+            "uci" => return uci::run(None),
+            "xboard" => return xboard::run(None),
+            "perft" | "divide" => return perft::run(mode, &args[1..]),
+            "epd" => return epd::run(&args[1..]),
+            "match" => return match_runner::run(&args[1..]),
+            "selfplay" => return selfplay::run(&args[1..]),
+            "tune" => return tuner::run(&args[1..]),
+            // eval [fen]: every term of the evaluation, for the start position without a fen
+            "eval" => {
+                let board = if args.len() > 1 { chess::Board::from_str(&args[1..].join(" ")) } else { Ok(chess::Board::default()) };
+                match board {
+                    Ok(board) => print!("{}", eval::trace(board)),
+                    Err(e) => println!("invalid fen: {}", e),
+                }
+                return;
+            }
+            // bench [depth]: the node count signature of this build
+            "bench" => {
+                let depth = args.get(1).and_then(|d| d.parse().ok()).unwrap_or(bench::DEFAULT_BENCH_DEPTH);
+                return bench::run(depth);
+            }
+            _ => println!("unknown mode '{}', starting an interactive game", mode),
+        }
+    }
     
     println!("Welcome to the Chess Game!");
     println!("This is a simple chess game where you can play against a basic AI.");
     println!("The AI will make random moves, and you can enter your moves in standard algebraic notation.");
     println!("To make a move, enter the starting square and the ending square (e.g., e2e4).");
     println!("To exit the game, type 'exit'.");
+    println!("Type 'ponder' instead of a move to let the AI think on your time (type it again to stop).");
+    println!("Type 'multipv 3' to see the AI's top 3 candidate moves (and 'multipv 1' to go back).");
     println!("Let's start the game!");
     println!("What color do you wish to play as? (white/black):");
     let mut color = String::new();
@@ -18,6 +49,13 @@ fn main() {
         .read_line(&mut color)
         .expect("Failed to read line");
     let mut color = color.trim().to_lowercase();
+    // a gui talking to us will open with "uci" or "xboard" instead of picking a colour
+    if color == "uci" {
+        return uci::run(Some(color));
+    }
+    if color == "xboard" {
+        return xboard::run(Some(color));
+    }
     if color != "white" && color != "black" {
         println!("Invalid color choice. Defaulting to white.");
         color = "white".to_string();
@@ -39,42 +77,114 @@ fn start_game(player_color: Color) {
 }
 
 fn continue_game(mut game: Game, player_color: Color, ai_color: Color) {
-    if player_color == game.side_to_move() {
-        println!("your move: ");
-        let mut player_move = String::new();
-        io::stdin()
-            .read_line(&mut player_move)
-            .expect("Failed to read line");
-        let player_move = player_move.trim();
-        let square1 = Square::from_string(player_move[..2].to_string()).expect("square 1 missing");
-        let square2 = Square::from_string(player_move[2..4].to_string()).expect("square 2 missing");
-        let promotion = if player_move.len() > 4 {
-            let promotion_piece = match player_move[4..5].to_lowercase().as_str() {
-                "q" => Some(Piece::Queen),
-                "r" => Some(Piece::Rook),
-                "b" => Some(Piece::Bishop),
-                "n" => Some(Piece::Knight),
-                _ => None,
+    let engine = Engine::new();
+    let mut limits = SearchLimits {
+        movetime: Some(engine::DEFAULT_MOVE_TIME),
+        ..SearchLimits::default()
+    };
+    let mut ponder_enabled = false;
+    // the search running on the player's time, with the reply it is betting on
+    let mut ponder_search: Option<(ChessMove, SearchHandle)> = None;
+    // a ponder search the player walked into, it becomes the AI's next search
+    let mut ponder_hit: Option<SearchHandle> = None;
+    loop {
+        let position = game.current_position();
+        match position.status() {
+            BoardStatus::Checkmate => {
+                let winner = if position.side_to_move() == player_color { "The AI" } else { "You" };
+                println!("Checkmate! {} won.", winner);
+                break;
+            }
+            BoardStatus::Stalemate => {
+                println!("Stalemate, it's a draw.");
+                break;
+            }
+            BoardStatus::Ongoing => {}
+        }
+        if player_color == game.side_to_move() {
+            println!("your move: ");
+            let mut player_move = String::new();
+            let read = io::stdin()
+                .read_line(&mut player_move)
+                .expect("Failed to read line");
+            let player_move = player_move.trim();
+            if read == 0 || player_move == "exit" {
+                if let Some((_, handle)) = ponder_search.take() {
+                    handle.stop();
+                    handle.join();
+                }
+                break;
+            }
+            if player_move == "ponder" {
+                ponder_enabled = !ponder_enabled;
+                println!("pondering {}", if ponder_enabled { "on" } else { "off" });
+                continue;
+            }
+            // show the AI's top n candidate moves after each of its moves
+            if let Some(lines) = player_move.strip_prefix("multipv ").and_then(|n| n.trim().parse::<usize>().ok()) {
+                limits.multi_pv = lines.clamp(1, engine::MAX_MULTI_PV);
+                println!("showing {} line(s)", limits.multi_pv);
+                continue;
+            }
+            // anything that isn't a legal move gets the prompt again
+            let chess_move = match ChessMove::from_str(&player_move.to_lowercase()) {
+                Ok(chess_move) if game.current_position().legal(chess_move) => chess_move,
+                _ => {
+                    println!("illegal move: {}", player_move);
+                    continue;
+                }
+            };
+            game.make_move(chess_move);
+            if let Some((expected, handle)) = ponder_search.take() {
+                if expected == chess_move {
+                    println!("ponder hit!");
+                    handle.ponderhit();
+                    ponder_hit = Some(handle);
+                } else {
+                    handle.stop();
+                    handle.join();
+                }
+            }
+        }
+        else
+        {
+            println!("current board eval: {}", eval::evaluate(game.current_position(), ai_color));
+            let timer = Instant::now();
+            let result = match ponder_hit.take() {
+                Some(handle) => handle.join(),
+                None => engine.search(game.current_position(), &limits, &SearchSignals::default(), |_| {}),
             };
-            promotion_piece
-        } else {
-            None
-        };
-        let chess_move = ChessMove::new(square1, square2, promotion);
-        game.make_move(chess_move);
-        continue_game(game, player_color, ai_color); 
-   }
-   else
-   {
-        println!("current board eval: {}", eval::evaluate(game.current_position(), ai_color));
-        let timer = Instant::now();
-        let ai_move = engine::engine_move(game.current_position(), ai_color);
-        let elapsed = timer.elapsed();
-        println!("Time taken for AI move: {:?}", elapsed);
-        println!("AI's move: {}", ai_move);
-        game.make_move(ai_move);
-        continue_game(game, player_color, ai_color);
-   }
+            // the game isn't over, so even the shortest search has a move
+            let Some(ai_move) = result.best_move else {
+                println!("the AI couldn't find a move");
+                break;
+            };
+            let elapsed = timer.elapsed();
+            println!("Time taken for AI move: {:?}", elapsed);
+            println!("AI's move: {}", ai_move);
+            if result.lines.len() > 1 {
+                println!("AI's candidate moves at depth {}:", result.depth);
+                for (rank, line) in result.lines.iter().enumerate() {
+                    println!("  {}. ({}) {}", rank + 1, line.score, line.pv_string());
+                }
+            } else if result.pv.len() > 1 {
+                println!("AI expects: {}", result.pv_string());
+            }
+            game.make_move(ai_move);
+
+            // think about the position after the reply we expect while the player thinks
+            if ponder_enabled && let Some(reply) = result.ponder_move {
+                let ponder_limits = SearchLimits {
+                    ponder: true,
+                    ..limits.clone()
+                };
+                println!("AI is thinking on your time, expecting {}", reply);
+                let ponder_board = game.current_position().make_move_new(reply);
+                let handle = engine.spawn_search(ponder_board, ponder_limits, |_| {}, |_| {});
+                ponder_search = Some((reply, handle));
+            }
+        }
+    }
 }
 
 // fn evaluate() {
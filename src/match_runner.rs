@@ -0,0 +1,739 @@
+use chess::{Board, BoardStatus, ChessMove, Color, Piece};
+use crate::engine::{Engine, SearchLimits, SearchSignals, MATE_SCORE};
+use crate::epd;
+use crate::pgn;
+use crate::sprt::{Sprt, SprtResult, Wdl};
+use crate::timeman::Clock;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// engine vs engine matches: two engines, each either this engine (with its own settings) or an
+// external uci binary, play pairs of games from every opening with colours swapped.
+// the command line follows cutechess-cli:
+//
+//   match -engine name=new -engine name=old cmd=./old-build -each tc=10+0.1 -games 400
+//         -concurrency 4 -openings file=book.epd -pgnout games.pgn
+//         -sprt elo0=0 elo1=5 alpha=0.05 beta=0.05 -draw movenumber=40 movecount=8 score=10
+//         -resign movecount=3 score=600 -maxmoves 200
+
+const USAGE: &str = "usage: match -engine [name=N] [cmd=PATH] [tc=BASE+INC | st=SECONDS | depth=N | nodes=N] [option.NAME=VALUE]... \
+-engine ... [-each ...] [-games N] [-concurrency N] [-openings file=FILE] [-pgnout FILE] \
+[-sprt elo0=E0 elo1=E1 alpha=A beta=B] [-draw movenumber=N movecount=N score=CP] [-resign movecount=N score=CP] [-maxmoves N] [-timemargin MS]";
+
+// a uci engine gets this long on top of its own time before we give up waiting on it
+const UCI_GRACE: Duration = Duration::from_secs(5);
+// how long an engine may take to start up or answer isready
+const UCI_STARTUP: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy)]
+enum TimeControl {
+    // base time for the game plus an increment per move
+    Clock { base: Duration, increment: Duration },
+    MoveTime(Duration),
+    Depth(u32),
+    Nodes(u64),
+}
+
+#[derive(Clone)]
+struct EngineConfig {
+    name: String,
+    // None plays this engine in-process
+    command: Option<String>,
+    // uci options, for this engine only Hash and Threads mean anything
+    options: Vec<(String, String)>,
+    time_control: TimeControl,
+}
+
+struct DrawAdjudication {
+    // not before this move number
+    move_number: usize,
+    // for this many moves in a row both engines must be within `score` of 0
+    move_count: u32,
+    score: i32,
+}
+
+struct ResignAdjudication {
+    // an engine that says it is `score` behind this many moves in a row loses
+    move_count: u32,
+    score: i32,
+}
+
+struct Settings {
+    games: usize,
+    concurrency: usize,
+    openings: Vec<Board>,
+    pgn_path: Option<String>,
+    sprt: Option<Sprt>,
+    draw: Option<DrawAdjudication>,
+    resign: Option<ResignAdjudication>,
+    // the game is a draw after this many moves by each side, 0 for no limit
+    max_moves: usize,
+    // how far an engine may overstep its clock before it loses on time
+    time_margin: Duration,
+}
+
+// both clocks during a game, indexed by Color::to_index
+struct GameClock {
+    time_left: [Duration; 2],
+    increment: [Duration; 2],
+}
+
+// something that plays moves: this engine or an external one
+trait Player {
+    fn new_game(&mut self) -> Result<(), String>;
+    // the move for `board` (reached from `start` by `moves`) and, if the player says, its score
+    // in centipawns from its own point of view
+    fn play(&mut self, start: &Board, moves: &[ChessMove], board: &Board, clock: &GameClock) -> Result<(ChessMove, Option<i32>), String>;
+}
+
+struct InternalPlayer {
+    engine: Engine,
+    time_control: TimeControl,
+}
+
+impl Player for InternalPlayer {
+    fn new_game(&mut self) -> Result<(), String> {
+        self.engine.new_game();
+        Ok(())
+    }
+
+    fn play(&mut self, _start: &Board, _moves: &[ChessMove], board: &Board, clock: &GameClock) -> Result<(ChessMove, Option<i32>), String> {
+        let side = board.side_to_move().to_index();
+        let limits = match self.time_control {
+            TimeControl::Clock { .. } => SearchLimits {
+                clock: Some(Clock {
+                    time_left: clock.time_left[side],
+                    increment: clock.increment[side],
+                    moves_to_go: None,
+                }),
+                move_overhead: Duration::from_millis(10),
+                ..SearchLimits::default()
+            },
+            TimeControl::MoveTime(movetime) => SearchLimits {
+                movetime: Some(movetime),
+                ..SearchLimits::default()
+            },
+            TimeControl::Depth(depth) => SearchLimits {
+                depth: Some(depth),
+                ..SearchLimits::default()
+            },
+            TimeControl::Nodes(nodes) => SearchLimits {
+                nodes: Some(nodes),
+                ..SearchLimits::default()
+            },
+        };
+        let result = self.engine.search(*board, &limits, &SearchSignals::default(), |_| {});
+        match result.best_move {
+            Some(m) => Ok((m, Some(result.score))),
+            None => Err("no move".to_string()),
+        }
+    }
+}
+
+// an external uci engine running as a child process. its output is read on a thread of its own
+// so a hung engine can't hang the match
+struct UciPlayer {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+    time_control: TimeControl,
+}
+
+impl UciPlayer {
+    fn start(config: &EngineConfig, command: &str) -> Result<Self, String> {
+        let mut parts = command.split_whitespace();
+        let program = parts.next().ok_or("empty engine command")?;
+        let mut child = Command::new(program)
+            .args(parts)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("can't start {}: {}", command, e))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut player = UciPlayer {
+            child,
+            stdin,
+            lines,
+            time_control: config.time_control,
+        };
+        player.send("uci")?;
+        player.wait_for("uciok", UCI_STARTUP)?;
+        for (name, value) in &config.options {
+            player.send(&format!("setoption name {} value {}", name, value))?;
+        }
+        player.send("isready")?;
+        player.wait_for("readyok", UCI_STARTUP)?;
+        Ok(player)
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{}", command).map_err(|_| "engine went away".to_string())
+    }
+
+    // reads lines until one starts with `token`, returns that line
+    fn wait_for(&mut self, token: &str, timeout: Duration) -> Result<String, String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.lines.recv_timeout(left) {
+                Ok(line) if line.split_whitespace().next() == Some(token) => return Ok(line),
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => return Err(format!("no {} from the engine", token)),
+                Err(RecvTimeoutError::Disconnected) => return Err("engine went away".to_string()),
+            }
+        }
+    }
+}
+
+impl Player for UciPlayer {
+    fn new_game(&mut self) -> Result<(), String> {
+        self.send("ucinewgame")?;
+        self.send("isready")?;
+        self.wait_for("readyok", UCI_STARTUP).map(|_| ())
+    }
+
+    fn play(&mut self, start: &Board, moves: &[ChessMove], _board: &Board, clock: &GameClock) -> Result<(ChessMove, Option<i32>), String> {
+        let mut position = if *start == Board::default() {
+            "position startpos".to_string()
+        } else {
+            format!("position fen {}", start)
+        };
+        if !moves.is_empty() {
+            position.push_str(" moves");
+            for m in moves {
+                position.push_str(&format!(" {}", m));
+            }
+        }
+        self.send(&position)?;
+        let (go, timeout) = match self.time_control {
+            TimeControl::Clock { .. } => (
+                format!(
+                    "go wtime {} btime {} winc {} binc {}",
+                    clock.time_left[0].as_millis(),
+                    clock.time_left[1].as_millis(),
+                    clock.increment[0].as_millis(),
+                    clock.increment[1].as_millis()
+                ),
+                clock.time_left[0].max(clock.time_left[1]) + UCI_GRACE,
+            ),
+            TimeControl::MoveTime(movetime) => (format!("go movetime {}", movetime.as_millis()), movetime + UCI_GRACE),
+            // no telling how long these take
+            TimeControl::Depth(depth) => (format!("go depth {}", depth), Duration::from_secs(3600)),
+            TimeControl::Nodes(nodes) => (format!("go nodes {}", nodes), Duration::from_secs(3600)),
+        };
+        self.send(&go)?;
+
+        let deadline = Instant::now() + timeout;
+        let mut score = None;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let line = match self.lines.recv_timeout(left) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => return Err("no bestmove from the engine".to_string()),
+                Err(RecvTimeoutError::Disconnected) => return Err("engine went away".to_string()),
+            };
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            match tokens.first() {
+                Some(&"info") => {
+                    if let Some(i) = tokens.iter().position(|t| *t == "score") {
+                        let value = tokens.get(i + 2).and_then(|v| v.parse::<i32>().ok());
+                        score = match (tokens.get(i + 1), value) {
+                            (Some(&"cp"), Some(cp)) => Some(cp),
+                            (Some(&"mate"), Some(moves)) if moves > 0 => Some(MATE_SCORE - moves),
+                            (Some(&"mate"), Some(moves)) => Some(-MATE_SCORE - moves),
+                            _ => score,
+                        };
+                    }
+                }
+                Some(&"bestmove") => {
+                    let text = tokens.get(1).copied().unwrap_or("");
+                    let m = text.parse::<ChessMove>().map_err(|_| format!("bad bestmove {}", text))?;
+                    return Ok((m, score));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Drop for UciPlayer {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        // give it a moment to exit by itself
+        for _ in 0..50 {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn make_player(config: &EngineConfig) -> Result<Box<dyn Player>, String> {
+    if let Some(command) = &config.command {
+        return Ok(Box::new(UciPlayer::start(config, command)?));
+    }
+    let mut engine = Engine::new();
+    for (name, value) in &config.options {
+        let value = value.parse::<usize>().map_err(|_| format!("bad value for {}: {}", name, value))?;
+        match name.to_lowercase().as_str() {
+            "hash" => engine.set_hash_size(value),
+            "threads" => engine.set_threads(value),
+            _ => return Err(format!("{}: unknown option {}", config.name, name)),
+        }
+    }
+    Ok(Box::new(InternalPlayer {
+        engine,
+        time_control: config.time_control,
+    }))
+}
+
+// one finished game
+struct GameRecord {
+    round: usize,
+    // whether engine 1 had white
+    first_is_white: bool,
+    start: Board,
+    moves: Vec<ChessMove>,
+    result: &'static str,
+    termination: String,
+}
+
+fn loss(side: Color) -> &'static str {
+    match side {
+        Color::White => "0-1",
+        Color::Black => "1-0",
+    }
+}
+
+// draws and mates by the rules. `hashes` holds the positions since the last capture or pawn move
+pub fn game_over(board: &Board, hashes: &[u64], halfmoves: u32) -> Option<(&'static str, String)> {
+    match board.status() {
+        BoardStatus::Checkmate => return Some((loss(board.side_to_move()), "checkmate".to_string())),
+        BoardStatus::Stalemate => return Some(("1/2-1/2", "stalemate".to_string())),
+        BoardStatus::Ongoing => {}
+    }
+    let current = board.get_hash();
+    if hashes.iter().filter(|hash| **hash == current).count() >= 3 {
+        return Some(("1/2-1/2", "threefold repetition".to_string()));
+    }
+    if halfmoves >= 100 {
+        return Some(("1/2-1/2", "fifty move rule".to_string()));
+    }
+    let pieces = board.combined().popcnt();
+    let minors = (board.pieces(Piece::Knight) | board.pieces(Piece::Bishop)).popcnt();
+    if pieces == 2 || (pieces == 3 && minors == 1) {
+        return Some(("1/2-1/2", "insufficient material".to_string()));
+    }
+    None
+}
+
+fn play_game(white: &mut dyn Player, black: &mut dyn Player, time_controls: [TimeControl; 2], start: Board, settings: &Settings) -> (Vec<ChessMove>, &'static str, String) {
+    let mut moves = Vec::new();
+    if let Err(e) = white.new_game() {
+        return (moves, loss(Color::White), format!("white: {}", e));
+    }
+    if let Err(e) = black.new_game() {
+        return (moves, loss(Color::Black), format!("black: {}", e));
+    }
+    let mut clock = GameClock {
+        time_left: [Duration::ZERO; 2],
+        increment: [Duration::ZERO; 2],
+    };
+    for (i, time_control) in time_controls.iter().enumerate() {
+        if let TimeControl::Clock { base, increment } = time_control {
+            clock.time_left[i] = *base;
+            clock.increment[i] = *increment;
+        }
+    }
+    let mut board = start;
+    let mut hashes = vec![board.get_hash()];
+    let mut halfmoves = 0;
+    let mut draw_plies = 0;
+    let mut resign_moves = [0; 2];
+    loop {
+        if let Some((result, reason)) = game_over(&board, &hashes, halfmoves) {
+            return (moves, result, reason);
+        }
+        if settings.max_moves > 0 && moves.len() >= settings.max_moves * 2 {
+            return (moves, "1/2-1/2", "move limit".to_string());
+        }
+        let side = board.side_to_move();
+        let index = side.to_index();
+        let timer = Instant::now();
+        let reply = match side {
+            Color::White => white.play(&start, &moves, &board, &clock),
+            Color::Black => black.play(&start, &moves, &board, &clock),
+        };
+        let elapsed = timer.elapsed();
+        let (m, score) = match reply {
+            Ok(reply) => reply,
+            Err(e) => return (moves, loss(side), e),
+        };
+        if let TimeControl::Clock { .. } = time_controls[index] {
+            if elapsed > clock.time_left[index] + settings.time_margin {
+                return (moves, loss(side), "loses on time".to_string());
+            }
+            clock.time_left[index] = clock.time_left[index].saturating_sub(elapsed) + clock.increment[index];
+        }
+        if !board.legal(m) {
+            return (moves, loss(side), format!("illegal move {}", m));
+        }
+
+        let irreversible = board.piece_on(m.get_source()) == Some(Piece::Pawn) || board.piece_on(m.get_dest()).is_some();
+        board = board.make_move_new(m);
+        moves.push(m);
+        if irreversible {
+            halfmoves = 0;
+            hashes.clear();
+        } else {
+            halfmoves += 1;
+        }
+        hashes.push(board.get_hash());
+
+        // adjudication goes by what the engines say about their own position
+        if let Some(resign) = &settings.resign {
+            resign_moves[index] = if score.is_some_and(|score| score <= -resign.score) { resign_moves[index] + 1 } else { 0 };
+            if resign_moves[index] >= resign.move_count {
+                return (moves, loss(side), "adjudication: resign".to_string());
+            }
+        }
+        if let Some(draw) = &settings.draw {
+            let quiet = score.is_some_and(|score| score.abs() <= draw.score);
+            draw_plies = if quiet && moves.len() >= draw.move_number * 2 { draw_plies + 1 } else { 0 };
+            if draw_plies >= draw.move_count * 2 {
+                return (moves, "1/2-1/2", "adjudication: draw".to_string());
+            }
+        }
+    }
+}
+
+pub fn run(args: &[String]) {
+    let (configs, settings) = match parse_args(args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            println!("{}", e);
+            println!("{}", USAGE);
+            return;
+        }
+    };
+    let mut pgn_out = match &settings.pgn_path {
+        Some(path) => match File::create(path) {
+            Ok(file) => Some(BufWriter::new(file)),
+            Err(e) => {
+                println!("can't write {}: {}", path, e);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    println!("{} vs {}, {} games, {} at a time", configs[0].name, configs[1].name, settings.games, settings.concurrency);
+    let next_game = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let mut wdl = Wdl::default();
+    let mut sprt_result = SprtResult::Continue;
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel::<Result<GameRecord, String>>();
+        for _ in 0..settings.concurrency {
+            let sender = sender.clone();
+            let (configs, settings, next_game, stop) = (&configs, &settings, &next_game, &stop);
+            scope.spawn(move || {
+                // every worker has its own pair of engines (and tables, and processes)
+                let (mut first, mut second) = match (make_player(&configs[0]), make_player(&configs[1])) {
+                    (Ok(first), Ok(second)) => (first, second),
+                    (Err(e), _) | (_, Err(e)) => {
+                        let _ = sender.send(Err(e));
+                        return;
+                    }
+                };
+                let time_controls = [configs[0].time_control, configs[1].time_control];
+                while !stop.load(Ordering::Relaxed) {
+                    let round = next_game.fetch_add(1, Ordering::Relaxed);
+                    if round >= settings.games {
+                        break;
+                    }
+                    // every opening twice, once with each engine on white
+                    let start = settings.openings[(round / 2) % settings.openings.len()];
+                    let first_is_white = round.is_multiple_of(2);
+                    let (moves, result, termination) = if first_is_white {
+                        play_game(&mut *first, &mut *second, time_controls, start, settings)
+                    } else {
+                        let swapped = [time_controls[1], time_controls[0]];
+                        play_game(&mut *second, &mut *first, swapped, start, settings)
+                    };
+                    let record = GameRecord {
+                        round,
+                        first_is_white,
+                        start,
+                        moves,
+                        result,
+                        termination,
+                    };
+                    if sender.send(Ok(record)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for message in receiver {
+            let record = match message {
+                Ok(record) => record,
+                Err(e) => {
+                    println!("{}", e);
+                    stop.store(true, Ordering::Relaxed);
+                    continue;
+                }
+            };
+            // games still running when the sprt finished don't count
+            if sprt_result != SprtResult::Continue {
+                continue;
+            }
+            let (white, black) = if record.first_is_white { (&configs[0].name, &configs[1].name) } else { (&configs[1].name, &configs[0].name) };
+            match (record.result, record.first_is_white) {
+                ("1-0", true) | ("0-1", false) => wdl.wins += 1,
+                ("0-1", true) | ("1-0", false) => wdl.losses += 1,
+                _ => wdl.draws += 1,
+            }
+            println!("Finished game {} ({} vs {}): {} {{{}}}", record.round + 1, white, black, record.result, record.termination);
+            print_score(&configs, &wdl, settings.sprt.as_ref());
+
+            if let Some(out) = &mut pgn_out {
+                let tags = [
+                    ("Event", format!("{} match", env!("CARGO_PKG_NAME"))),
+                    ("Site", "?".to_string()),
+                    ("Date", pgn::today()),
+                    ("Round", (record.round + 1).to_string()),
+                    ("White", white.clone()),
+                    ("Black", black.clone()),
+                    ("Termination", record.termination.clone()),
+                ];
+                let written = pgn::write_game(out, &tags, &record.start, &record.moves, record.result).and_then(|_| out.flush());
+                if let Err(e) = written {
+                    println!("can't write pgn: {}", e);
+                    pgn_out = None;
+                }
+            }
+            if let Some(sprt) = &settings.sprt {
+                sprt_result = sprt.test(&wdl);
+                if sprt_result != SprtResult::Continue {
+                    stop.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+    });
+
+    println!("===========================");
+    print_score(&configs, &wdl, settings.sprt.as_ref());
+    match sprt_result {
+        SprtResult::AcceptH1 => println!("SPRT: H1 was accepted"),
+        SprtResult::AcceptH0 => println!("SPRT: H0 was accepted"),
+        SprtResult::Continue if settings.sprt.is_some() => println!("SPRT: no decision"),
+        SprtResult::Continue => {}
+    }
+}
+
+fn print_score(configs: &[EngineConfig], wdl: &Wdl, sprt: Option<&Sprt>) {
+    println!(
+        "Score of {} vs {}: {} - {} - {} [{:.3}] {}",
+        configs[0].name,
+        configs[1].name,
+        wdl.wins,
+        wdl.losses,
+        wdl.draws,
+        wdl.score(),
+        wdl.games()
+    );
+    let (elo, error) = wdl.elo();
+    println!("Elo difference: {:.1} +/- {:.1}", elo, error);
+    if let Some(sprt) = sprt {
+        let (lower, upper) = sprt.bounds();
+        println!("SPRT: llr {:.2} ({:.2}, {:.2}) [{:.1}, {:.1}]", sprt.llr(wdl), lower, upper, sprt.elo0, sprt.elo1);
+    }
+}
+
+// key=value, the value may itself contain '=' (option.Name=value)
+fn key_value(text: &str) -> Result<(&str, &str), String> {
+    text.split_once('=').ok_or_else(|| format!("expected key=value, got {}", text))
+}
+
+fn parse_number<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("not a number: {}", text))
+}
+
+fn seconds(text: &str) -> Result<Duration, String> {
+    let secs: f64 = parse_number(text)?;
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("bad time: {}", text))
+}
+
+fn apply_engine_option(config: &mut EngineConfig, key: &str, value: &str) -> Result<(), String> {
+    match key {
+        "name" => config.name = value.to_string(),
+        "cmd" => config.command = Some(value.to_string()),
+        // base+increment in seconds, or just the base
+        "tc" => {
+            let (base, increment) = value.split_once('+').unwrap_or((value, "0"));
+            config.time_control = TimeControl::Clock {
+                base: seconds(base)?,
+                increment: seconds(increment)?,
+            };
+        }
+        "st" => config.time_control = TimeControl::MoveTime(seconds(value)?),
+        "depth" => config.time_control = TimeControl::Depth(parse_number(value)?),
+        "nodes" => config.time_control = TimeControl::Nodes(parse_number(value)?),
+        _ => match key.strip_prefix("option.") {
+            Some(name) => config.options.push((name.to_string(), value.to_string())),
+            None => return Err(format!("unknown engine setting {}", key)),
+        },
+    }
+    Ok(())
+}
+
+fn parse_args(args: &[String]) -> Result<(Vec<EngineConfig>, Settings), String> {
+    // split into -flag value value... groups
+    let mut groups: Vec<(&str, Vec<&str>)> = Vec::new();
+    for arg in args {
+        match arg.strip_prefix('-') {
+            Some(flag) => groups.push((flag, Vec::new())),
+            None => match groups.last_mut() {
+                Some((_, values)) => values.push(arg),
+                None => return Err(format!("unexpected {}", arg)),
+            },
+        }
+    }
+
+    let mut engines: Vec<Vec<&str>> = Vec::new();
+    let mut each: Vec<&str> = Vec::new();
+    let mut settings = Settings {
+        games: 100,
+        concurrency: 1,
+        openings: Vec::new(),
+        pgn_path: None,
+        sprt: None,
+        draw: None,
+        resign: None,
+        max_moves: 0,
+        time_margin: Duration::from_millis(50),
+    };
+    for (flag, values) in groups {
+        let single = || values.first().copied().ok_or_else(|| format!("-{} needs a value", flag));
+        match flag {
+            "engine" => engines.push(values.clone()),
+            "each" => each.extend(values.iter()),
+            "games" => settings.games = parse_number(single()?)?,
+            "concurrency" => settings.concurrency = parse_number::<usize>(single()?)?.max(1),
+            "pgnout" => settings.pgn_path = Some(single()?.to_string()),
+            "maxmoves" => settings.max_moves = parse_number(single()?)?,
+            "timemargin" => settings.time_margin = Duration::from_millis(parse_number(single()?)?),
+            "openings" => {
+                for value in &values {
+                    match key_value(value)? {
+                        ("file", path) => settings.openings = load_openings(path)?,
+                        // we only play the file front to back
+                        ("order", "sequential") | ("format", _) => {}
+                        _ => return Err(format!("unknown opening setting {}", value)),
+                    }
+                }
+            }
+            "sprt" => {
+                let mut sprt = Sprt::default();
+                for value in &values {
+                    let (key, value) = key_value(value)?;
+                    let field = match key {
+                        "elo0" => &mut sprt.elo0,
+                        "elo1" => &mut sprt.elo1,
+                        "alpha" => &mut sprt.alpha,
+                        "beta" => &mut sprt.beta,
+                        _ => return Err(format!("unknown sprt setting {}", key)),
+                    };
+                    *field = parse_number(value)?;
+                }
+                settings.sprt = Some(sprt);
+            }
+            "draw" => {
+                let mut draw = DrawAdjudication {
+                    move_number: 40,
+                    move_count: 8,
+                    score: 10,
+                };
+                for value in &values {
+                    match key_value(value)? {
+                        ("movenumber", n) => draw.move_number = parse_number(n)?,
+                        ("movecount", n) => draw.move_count = parse_number(n)?,
+                        ("score", n) => draw.score = parse_number(n)?,
+                        (key, _) => return Err(format!("unknown draw setting {}", key)),
+                    }
+                }
+                settings.draw = Some(draw);
+            }
+            "resign" => {
+                let mut resign = ResignAdjudication { move_count: 3, score: 600 };
+                for value in &values {
+                    match key_value(value)? {
+                        ("movecount", n) => resign.move_count = parse_number(n)?,
+                        ("score", n) => resign.score = parse_number(n)?,
+                        (key, _) => return Err(format!("unknown resign setting {}", key)),
+                    }
+                }
+                settings.resign = Some(resign);
+            }
+            _ => return Err(format!("unknown flag -{}", flag)),
+        }
+    }
+
+    if engines.len() != 2 {
+        return Err("a match needs exactly two -engine".to_string());
+    }
+    let mut configs = Vec::new();
+    for (i, engine) in engines.iter().enumerate() {
+        let mut config = EngineConfig {
+            name: format!("engine{}", i + 1),
+            command: None,
+            options: Vec::new(),
+            time_control: TimeControl::MoveTime(Duration::from_millis(100)),
+        };
+        // -each first so an engine's own settings win
+        for value in each.iter().chain(engine.iter()) {
+            let (key, value) = key_value(value)?;
+            apply_engine_option(&mut config, key, value)?;
+        }
+        configs.push(config);
+    }
+    if settings.openings.is_empty() {
+        settings.openings.push(Board::default());
+    }
+    Ok((configs, settings))
+}
+
+// one position per line, fen or epd (operations are ignored)
+pub fn load_openings(path: &str) -> Result<Vec<Board>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    let openings: Vec<Board> = text
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|line| epd::parse_line(line).map(|entry| entry.board))
+        .collect::<Result<_, _>>()?;
+    if openings.is_empty() {
+        return Err(format!("no openings in {}", path));
+    }
+    Ok(openings)
+}
@@ -0,0 +1,197 @@
+use chess::{get_bishop_moves, get_knight_moves, get_rook_moves, Board, Color, Piece, Square};
+use crate::attacks::pawn_attacks;
+use crate::eval::{EvalParams, Sides};
+use crate::pawns::{adjacent_files, file_mask, ranks_ahead};
+
+// piece activity: how many squares each piece can safely go to (not onto our own pieces, not
+// where their pawns take it) looked up in a table per piece, plus the usual patterns below
+
+// pattern terms, the index into EvalParams::activity_mid/_end
+// no pawns on the rook's file, then none of ours
+pub const ROOK_OPEN_FILE: usize = 0;
+pub const ROOK_SEMI_OPEN_FILE: usize = 1;
+// on the seventh with their king on the eighth or their pawns still on the seventh
+pub const ROOK_SEVENTH: usize = 2;
+// fourth to sixth rank, defended by a pawn and no pawn of theirs can ever chase it away
+pub const KNIGHT_OUTPOST: usize = 3;
+pub const BISHOP_PAIR: usize = 4;
+// per pawn of ours on the bishop's colour
+pub const BAD_BISHOP: usize = 5;
+// on a7/h7 (or a6/h6) shut in by a pawn on b6/g6 (b5/g5), flipped for black
+pub const TRAPPED_BISHOP: usize = 6;
+// shut in on the back rank by its own king, which walked over instead of castling
+pub const TRAPPED_ROOK: usize = 7;
+pub const ACTIVITY_TERMS: usize = 8;
+
+// table lengths, one entry for every count from 0 up to the most squares each piece can reach
+pub const KNIGHT_MOBILITY: usize = 9;
+pub const BISHOP_MOBILITY: usize = 14;
+pub const ROOK_MOBILITY: usize = 15;
+pub const QUEEN_MOBILITY: usize = 28;
+
+// a rook with this few moves next to its own king counts as trapped
+const TRAPPED_ROOK_MOBILITY: u32 = 3;
+const LIGHT_SQUARES: u64 = 0x55aa55aa55aa55aa;
+
+// (mobility, the pattern terms above) for each side
+pub fn evaluate(board: &Board, params: &EvalParams) -> (Sides, Sides) {
+    let (white_mobility, white_activity) = evaluate_side(board, Color::White, params);
+    let (black_mobility, black_activity) = evaluate_side(board, Color::Black, params);
+    ([white_mobility, black_mobility], [white_activity, black_activity])
+}
+
+fn evaluate_side(board: &Board, color: Color, params: &EvalParams) -> ((i32, i32), (i32, i32)) {
+    let ours = *board.color_combined(color);
+    let occupied = *board.combined();
+    let pawns = board.pieces(Piece::Pawn).0;
+    let our_pawns = pawns & ours.0;
+    let their_pawns = pawns & !ours.0;
+    let area = !ours.0 & !pawn_attacks(!color, their_pawns);
+    let (mut mid, mut end) = (0, 0);
+    let mut terms = [0; ACTIVITY_TERMS];
+
+    for square in board.pieces(Piece::Knight) & ours {
+        let count = (get_knight_moves(square).0 & area).count_ones() as usize;
+        mid += params.knight_mobility_mid[count];
+        end += params.knight_mobility_end[count];
+        let relative_rank = relative_rank(color, square);
+        let bit = 1u64 << square.to_index();
+        let chasers = their_pawns & adjacent_files(square.get_file().to_index()) & ranks_ahead(color, square);
+        if (3..=5).contains(&relative_rank) && pawn_attacks(color, our_pawns) & bit != 0 && chasers == 0 {
+            terms[KNIGHT_OUTPOST] += 1;
+        }
+    }
+
+    let bishops = board.pieces(Piece::Bishop) & ours;
+    for square in bishops {
+        let count = (get_bishop_moves(square, occupied).0 & area).count_ones() as usize;
+        mid += params.bishop_mobility_mid[count];
+        end += params.bishop_mobility_end[count];
+        let bit = 1u64 << square.to_index();
+        let same_colour = if bit & LIGHT_SQUARES != 0 { LIGHT_SQUARES } else { !LIGHT_SQUARES };
+        terms[BAD_BISHOP] += (our_pawns & same_colour).count_ones() as i32;
+        if is_trapped_bishop(color, square, their_pawns) {
+            terms[TRAPPED_BISHOP] += 1;
+        }
+    }
+    if bishops.popcnt() >= 2 {
+        terms[BISHOP_PAIR] += 1;
+    }
+
+    let king = board.king_square(color);
+    let their_king = board.king_square(!color);
+    for square in board.pieces(Piece::Rook) & ours {
+        let moves = (get_rook_moves(square, occupied).0 & area).count_ones();
+        mid += params.rook_mobility_mid[moves as usize];
+        end += params.rook_mobility_end[moves as usize];
+        let file = file_mask(square.get_file().to_index());
+        if pawns & file == 0 {
+            terms[ROOK_OPEN_FILE] += 1;
+        } else if our_pawns & file == 0 {
+            terms[ROOK_SEMI_OPEN_FILE] += 1;
+        }
+        let seventh = 0xffu64 << (8 * if color == Color::White { 6 } else { 1 });
+        if relative_rank(color, square) == 6 && (relative_rank(color, their_king) == 7 || their_pawns & seventh != 0) {
+            terms[ROOK_SEVENTH] += 1;
+        }
+        if moves <= TRAPPED_ROOK_MOBILITY && is_trapped_rook(color, square, king) {
+            terms[TRAPPED_ROOK] += 1;
+        }
+    }
+
+    for square in board.pieces(Piece::Queen) & ours {
+        let moves = get_bishop_moves(square, occupied) | get_rook_moves(square, occupied);
+        let count = (moves.0 & area).count_ones() as usize;
+        mid += params.queen_mobility_mid[count];
+        end += params.queen_mobility_end[count];
+    }
+
+    let (mut activity_mid, mut activity_end) = (0, 0);
+    for (term, count) in terms.iter().enumerate() {
+        activity_mid += count * params.activity_mid[term];
+        activity_end += count * params.activity_end[term];
+    }
+    ((mid, end), (activity_mid, activity_end))
+}
+
+fn relative_rank(color: Color, square: Square) -> usize {
+    let rank = square.get_rank().to_index();
+    if color == Color::White { rank } else { 7 - rank }
+}
+
+fn is_trapped_bishop(color: Color, square: Square, their_pawns: u64) -> bool {
+    // (bishop, pawn shutting it in) for white, black's are the same squares flipped
+    let traps = [(48, 41), (55, 46), (40, 33), (47, 38)];
+    traps.iter().any(|(bishop, pawn)| {
+        let (bishop, pawn) = if color == Color::White { (*bishop, *pawn) } else { (bishop ^ 56, pawn ^ 56) };
+        square.to_index() == bishop && their_pawns & (1 << pawn) != 0
+    })
+}
+
+// on the back rank in the corner, behind the king
+fn is_trapped_rook(color: Color, square: Square, king: Square) -> bool {
+    if relative_rank(color, square) != 0 || relative_rank(color, king) != 0 {
+        return false;
+    }
+    let king_file = king.get_file().to_index();
+    let rook_file = square.get_file().to_index();
+    (king_file >= 5 && rook_file > king_file) || (king_file <= 2 && rook_file < king_file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::DEFAULT_PARAMS;
+    use std::str::FromStr;
+
+    // mobility counts straight from the tables: a knight's squares in ones, a rook's in hundreds
+    fn params() -> EvalParams {
+        let mut params = EvalParams::from_vec(&vec![0; DEFAULT_PARAMS.to_vec().len()]);
+        params.knight_mobility_mid = std::array::from_fn(|count| count as i32);
+        params.rook_mobility_mid = std::array::from_fn(|count| 100 * count as i32);
+        params
+    }
+
+    fn mobility(fen: &str) -> [i32; 2] {
+        let (mobility, _) = evaluate(&Board::from_str(fen).unwrap(), &params());
+        [mobility[0].0, mobility[1].0]
+    }
+
+    #[test]
+    fn knight_and_rook_mobility() {
+        // e4 reaches eight squares, d2 is ours
+        assert_eq!(mobility("4k3/8/8/8/4N3/8/3P4/4K3 w - - 0 1"), [7, 0]);
+        // and b6 keeps it off c5
+        assert_eq!(mobility("4k3/8/1p6/8/4N3/8/3P4/4K3 w - - 0 1"), [6, 0]);
+        assert_eq!(mobility("7k/8/8/8/8/8/8/N6K w - - 0 1"), [2, 0]);
+        // up the file to a8 and along to the king
+        assert_eq!(mobility("4k3/8/8/8/8/8/8/R3K3 w - - 0 1"), [1000, 0]);
+        assert_eq!(mobility("4k3/8/8/8/8/1p6/8/R3K3 w - - 0 1"), [900, 0]);
+        // taking counts, going through doesn't
+        assert_eq!(mobility("4k3/8/8/n7/8/8/8/R3K3 w - - 0 1"), [700, 4]);
+        assert_eq!(mobility("r3k3/8/8/8/8/8/8/4K3 b - - 0 1"), [0, 1000]);
+        assert_eq!(mobility("r3k3/8/8/8/3n4/8/8/4K3 b - - 0 1"), [0, 1000 + 8]);
+    }
+
+    #[test]
+    fn activity_terms() {
+        let activity = |fen: &str, term: usize| {
+            let mut params = params();
+            params.activity_mid[term] = 1;
+            let (_, activity) = evaluate(&Board::from_str(fen).unwrap(), &params);
+            [activity[0].0, activity[1].0]
+        };
+        assert_eq!(activity("4k3/8/8/8/8/8/P7/R3K3 w - - 0 1", ROOK_SEMI_OPEN_FILE), [0, 0]);
+        assert_eq!(activity("4k3/p7/8/8/8/8/8/R3K3 w - - 0 1", ROOK_SEMI_OPEN_FILE), [1, 0]);
+        assert_eq!(activity("r3k3/8/8/8/8/8/8/R3K3 w - - 0 1", ROOK_OPEN_FILE), [1, 1]);
+        // d5 is covered by e4 and no black pawn can come to c6 or e6
+        assert_eq!(activity("4k3/8/8/3N4/4P3/8/8/4K3 w - - 0 1", KNIGHT_OUTPOST), [1, 0]);
+        assert_eq!(activity("4k3/2p5/8/3N4/4P3/8/8/4K3 w - - 0 1", KNIGHT_OUTPOST), [0, 0]);
+        assert_eq!(activity("4k3/8/8/4p3/3n4/8/8/4K3 w - - 0 1", KNIGHT_OUTPOST), [0, 1]);
+        // the king walked to f1 and shut the rook in
+        assert_eq!(activity("4k3/8/8/8/8/8/6PP/5K1R w - - 0 1", TRAPPED_ROOK), [1, 0]);
+        assert_eq!(activity("4k3/8/8/8/8/8/6PP/6KR w - - 0 1", TRAPPED_ROOK), [1, 0]);
+        assert_eq!(activity("4k3/8/8/8/8/8/6PP/R4K2 w - - 0 1", TRAPPED_ROOK), [0, 0]);
+        assert_eq!(activity("2bbk3/8/8/8/8/8/8/2B1K3 w - - 0 1", BISHOP_PAIR), [0, 1]);
+    }
+}
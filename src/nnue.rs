@@ -0,0 +1,467 @@
+use chess::{Board, Color, Piece, Square, ALL_PIECES};
+use std::fs;
+
+// efficiently updatable neural network eval. the network is 768 -> N (x2) -> 1:
+//
+// - one input per (colour, piece, square), from the point of view of one side: "ours" and
+//   "theirs" instead of white and black, and the board flipped for black. both sides share the
+//   same weights
+// - the hidden layer (the accumulator) is kept for both points of view. a move only changes a
+//   handful of inputs, so the accumulator is updated by adding and subtracting those weight
+//   columns instead of being computed again
+// - the output takes the side to move's accumulator then the other one, clipped to 0..QA, and
+//   gives the score for the side to move
+//
+// weights are i16 (hidden layer scaled by QA, output layer by QB), file layout (little endian):
+//
+//   b"PLSNNUE\0"  u32 version  u32 N
+//   i16 feature weights [768][N]  i16 feature biases [N]  i16 output weights [2N]
+//   i32 output bias  u32 checksum (fnv-1a of everything before it)
+
+pub const INPUTS: usize = 768;
+pub const QA: i32 = 255;
+pub const QB: i32 = 64;
+// network output to centipawns
+pub const SCALE: i32 = 400;
+pub const MAGIC: &[u8; 8] = b"PLSNNUE\0";
+pub const VERSION: u32 = 1;
+// the simd code works on 16 values at a time
+pub const MAX_HIDDEN: usize = 4096;
+pub const HIDDEN_ALIGN: usize = 16;
+// keeps the net out of mate score territory
+const MAX_EVAL: i32 = 30000;
+
+pub struct Network {
+    hidden: usize,
+    feature_weights: Vec<i16>,
+    feature_biases: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i32,
+}
+
+// input number of a piece on a square, seen from `perspective`
+pub fn feature_index(perspective: Color, color: Color, piece: Piece, square: Square) -> usize {
+    let square = if perspective == Color::White { square.to_index() } else { square.to_index() ^ 56 };
+    (color != perspective) as usize * 384 + piece.to_index() * 64 + square
+}
+
+fn fnv1a(bytes: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in bytes {
+        hash ^= *byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+// reads little endian values off the front of a byte slice
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, count: usize) -> &[u8] {
+        let (head, tail) = self.bytes.split_at(count);
+        self.bytes = tail;
+        head
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.take(4).try_into().unwrap())
+    }
+
+    fn i32(&mut self) -> i32 {
+        i32::from_le_bytes(self.take(4).try_into().unwrap())
+    }
+
+    fn i16s(&mut self, count: usize) -> Vec<i16> {
+        self.take(count * 2).chunks_exact(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect()
+    }
+}
+
+impl Network {
+    // a network straight from its quantized weights, laid out like in the file
+    pub fn new(hidden: usize, feature_weights: Vec<i16>, feature_biases: Vec<i16>, output_weights: Vec<i16>, output_bias: i32) -> Result<Network, String> {
+        if hidden == 0 || hidden > MAX_HIDDEN || !hidden.is_multiple_of(HIDDEN_ALIGN) {
+            return Err(format!("hidden layer size {} must be a multiple of {} up to {}", hidden, HIDDEN_ALIGN, MAX_HIDDEN));
+        }
+        if feature_weights.len() != INPUTS * hidden || feature_biases.len() != hidden || output_weights.len() != 2 * hidden {
+            return Err("weight counts don't match the hidden layer size".to_string());
+        }
+        Ok(Network {
+            hidden,
+            feature_weights,
+            feature_biases,
+            output_weights,
+            output_bias,
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Network, String> {
+        let bytes = fs::read(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+        Network::from_bytes(&bytes).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Network, String> {
+        let header = MAGIC.len() + 8;
+        if bytes.len() < header || &bytes[..MAGIC.len()] != MAGIC {
+            return Err("not a network file".to_string());
+        }
+        let mut reader = Reader { bytes: &bytes[MAGIC.len()..] };
+        let version = reader.u32();
+        if version != VERSION {
+            return Err(format!("network version {}, this engine reads version {}", version, VERSION));
+        }
+        let hidden = reader.u32() as usize;
+        if hidden == 0 || hidden > MAX_HIDDEN {
+            return Err(format!("bad hidden layer size {}", hidden));
+        }
+        let expected = header + (INPUTS * hidden + hidden + 2 * hidden) * 2 + 4 + 4;
+        if bytes.len() != expected {
+            return Err(format!("{} bytes, a network with {} hidden neurons has {}", bytes.len(), hidden, expected));
+        }
+        let checksum = u32::from_le_bytes(bytes[expected - 4..].try_into().unwrap());
+        if fnv1a(&bytes[..expected - 4]) != checksum {
+            return Err("checksum mismatch, the file is damaged".to_string());
+        }
+        let feature_weights = reader.i16s(INPUTS * hidden);
+        let feature_biases = reader.i16s(hidden);
+        let output_weights = reader.i16s(2 * hidden);
+        let output_bias = reader.i32();
+        Network::new(hidden, feature_weights, feature_biases, output_weights, output_bias)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.hidden as u32).to_le_bytes());
+        for weights in [&self.feature_weights, &self.feature_biases, &self.output_weights] {
+            for weight in weights.iter() {
+                bytes.extend_from_slice(&weight.to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(&self.output_bias.to_le_bytes());
+        let checksum = fnv1a(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_bytes()).map_err(|e| format!("can't write {}: {}", path, e))
+    }
+
+    pub fn hidden_size(&self) -> usize {
+        self.hidden
+    }
+
+    fn column(&self, feature: usize) -> &[i16] {
+        &self.feature_weights[feature * self.hidden..(feature + 1) * self.hidden]
+    }
+
+    // score in centipawns for the side to move
+    pub fn evaluate(&self, accumulator: &Accumulator, side_to_move: Color) -> i32 {
+        let (us, them) = if side_to_move == Color::White {
+            (&accumulator.white, &accumulator.black)
+        } else {
+            (&accumulator.black, &accumulator.white)
+        };
+        let (our_weights, their_weights) = self.output_weights.split_at(self.hidden);
+        let sum = simd::crelu_dot(us, our_weights) as i64 + simd::crelu_dot(them, their_weights) as i64 + self.output_bias as i64;
+        (sum * SCALE as i64 / (QA * QB) as i64).clamp(-MAX_EVAL as i64, MAX_EVAL as i64) as i32
+    }
+
+    // from scratch, slow. the search keeps accumulators around instead
+    pub fn evaluate_board(&self, board: &Board) -> i32 {
+        let mut accumulator = Accumulator::new(self);
+        accumulator.refresh(self, board);
+        self.evaluate(&accumulator, board.side_to_move())
+    }
+}
+
+// the hidden layer for both points of view
+#[derive(Clone)]
+pub struct Accumulator {
+    white: Vec<i16>,
+    black: Vec<i16>,
+}
+
+impl Accumulator {
+    pub fn new(network: &Network) -> Self {
+        Accumulator {
+            white: network.feature_biases.clone(),
+            black: network.feature_biases.clone(),
+        }
+    }
+
+    pub fn refresh(&mut self, network: &Network, board: &Board) {
+        self.white.copy_from_slice(&network.feature_biases);
+        self.black.copy_from_slice(&network.feature_biases);
+        for color in [Color::White, Color::Black] {
+            for piece in ALL_PIECES {
+                for square in *board.pieces(piece) & *board.color_combined(color) {
+                    self.add(network, color, piece, square);
+                }
+            }
+        }
+    }
+
+    fn add(&mut self, network: &Network, color: Color, piece: Piece, square: Square) {
+        simd::add_assign(&mut self.white, network.column(feature_index(Color::White, color, piece, square)));
+        simd::add_assign(&mut self.black, network.column(feature_index(Color::Black, color, piece, square)));
+    }
+
+    fn remove(&mut self, network: &Network, color: Color, piece: Piece, square: Square) {
+        simd::sub_assign(&mut self.white, network.column(feature_index(Color::White, color, piece, square)));
+        simd::sub_assign(&mut self.black, network.column(feature_index(Color::Black, color, piece, square)));
+    }
+
+    // this becomes `parent` (the accumulator of `before`) updated to `after`. the changed inputs
+    // come from comparing the piece bitboards, so captures, castling, en passant and
+    // promotions need no special cases
+    pub fn update_from(&mut self, parent: &Accumulator, network: &Network, before: &Board, after: &Board) {
+        self.white.copy_from_slice(&parent.white);
+        self.black.copy_from_slice(&parent.black);
+        for color in [Color::White, Color::Black] {
+            for piece in ALL_PIECES {
+                let old = *before.pieces(piece) & *before.color_combined(color);
+                let new = *after.pieces(piece) & *after.color_combined(color);
+                for square in old & !new {
+                    self.remove(network, color, piece, square);
+                }
+                for square in new & !old {
+                    self.add(network, color, piece, square);
+                }
+            }
+        }
+    }
+}
+
+// one accumulator per ply for the search. positions are copied rather than unmade, so making a
+// move fills in the next ply from the current one and going back is just using the ply below
+pub struct AccumulatorStack<'a> {
+    network: &'a Network,
+    accumulators: Vec<Accumulator>,
+}
+
+impl<'a> AccumulatorStack<'a> {
+    pub fn new(network: &'a Network, root: &Board) -> Self {
+        let mut root_accumulator = Accumulator::new(network);
+        root_accumulator.refresh(network, root);
+        AccumulatorStack {
+            network,
+            accumulators: vec![root_accumulator],
+        }
+    }
+
+    // `after` was reached from `before` (at `ply - 1`) with a move or a null move
+    pub fn push(&mut self, ply: usize, before: &Board, after: &Board) {
+        while self.accumulators.len() <= ply {
+            self.accumulators.push(Accumulator::new(self.network));
+        }
+        let (parents, children) = self.accumulators.split_at_mut(ply);
+        children[0].update_from(&parents[ply - 1], self.network, before, after);
+    }
+
+    pub fn evaluate(&self, ply: usize, side_to_move: Color) -> i32 {
+        self.network.evaluate(&self.accumulators[ply], side_to_move)
+    }
+}
+
+// avx2 where the cpu has it, plain loops otherwise. both wrap on overflow the same way so they
+// always agree
+mod simd {
+    use super::QA;
+
+    pub fn add_assign(values: &mut [i16], weights: &[i16]) {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: avx2 is there, and both slices are the hidden size (a multiple of 16)
+            unsafe { avx2::add_assign(values, weights) };
+            return;
+        }
+        for (value, weight) in values.iter_mut().zip(weights) {
+            *value = value.wrapping_add(*weight);
+        }
+    }
+
+    pub fn sub_assign(values: &mut [i16], weights: &[i16]) {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: as in add_assign
+            unsafe { avx2::sub_assign(values, weights) };
+            return;
+        }
+        for (value, weight) in values.iter_mut().zip(weights) {
+            *value = value.wrapping_sub(*weight);
+        }
+    }
+
+    // sum of clamp(value, 0, QA) * weight
+    pub fn crelu_dot(values: &[i16], weights: &[i16]) -> i32 {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: as in add_assign
+            return unsafe { avx2::crelu_dot(values, weights) };
+        }
+        values
+            .iter()
+            .zip(weights)
+            .fold(0i32, |sum, (value, weight)| sum.wrapping_add((*value as i32).clamp(0, QA) * *weight as i32))
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    mod avx2 {
+        use super::QA;
+        use std::arch::x86_64::*;
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn add_assign(values: &mut [i16], weights: &[i16]) {
+            for (value, weight) in values.chunks_exact_mut(16).zip(weights.chunks_exact(16)) {
+                unsafe {
+                    let sum = _mm256_add_epi16(_mm256_loadu_si256(value.as_ptr().cast()), _mm256_loadu_si256(weight.as_ptr().cast()));
+                    _mm256_storeu_si256(value.as_mut_ptr().cast(), sum);
+                }
+            }
+        }
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn sub_assign(values: &mut [i16], weights: &[i16]) {
+            for (value, weight) in values.chunks_exact_mut(16).zip(weights.chunks_exact(16)) {
+                unsafe {
+                    let difference = _mm256_sub_epi16(_mm256_loadu_si256(value.as_ptr().cast()), _mm256_loadu_si256(weight.as_ptr().cast()));
+                    _mm256_storeu_si256(value.as_mut_ptr().cast(), difference);
+                }
+            }
+        }
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn crelu_dot(values: &[i16], weights: &[i16]) -> i32 {
+            let zero = _mm256_setzero_si256();
+            let max = _mm256_set1_epi16(QA as i16);
+            let mut sum = _mm256_setzero_si256();
+            for (value, weight) in values.chunks_exact(16).zip(weights.chunks_exact(16)) {
+                unsafe {
+                    let clipped = _mm256_min_epi16(_mm256_max_epi16(_mm256_loadu_si256(value.as_ptr().cast()), zero), max);
+                    // multiplies pairs of i16 and adds neighbouring products into i32s
+                    sum = _mm256_add_epi32(sum, _mm256_madd_epi16(clipped, _mm256_loadu_si256(weight.as_ptr().cast())));
+                }
+            }
+            let mut lanes = [0i32; 8];
+            unsafe { _mm256_storeu_si256(lanes.as_mut_ptr().cast(), sum) };
+            lanes.iter().fold(0, |total, lane| total.wrapping_add(*lane))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+    use chess::{ChessMove, MoveGen};
+    use std::str::FromStr;
+
+    const HIDDEN: usize = 32;
+
+    fn random_network(hidden: usize) -> Network {
+        let mut rng = Rng::new(17);
+        let mut weights = |count: usize| -> Vec<i16> { (0..count).map(|_| rng.below(256) as i16 - 128).collect() };
+        let (feature_weights, feature_biases, output_weights) = (weights(INPUTS * hidden), weights(hidden), weights(2 * hidden));
+        Network::new(hidden, feature_weights, feature_biases, output_weights, 321).unwrap()
+    }
+
+    fn refreshed(network: &Network, board: &Board) -> Accumulator {
+        let mut accumulator = Accumulator::new(network);
+        accumulator.refresh(network, board);
+        accumulator
+    }
+
+    fn assert_same(updated: &Accumulator, refreshed: &Accumulator, board: &Board) {
+        assert!(updated.white == refreshed.white && updated.black == refreshed.black, "{}", board);
+    }
+
+    // a file the way to_bytes writes it, with any header and a right checksum
+    fn file(version: u32, hidden: usize) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&version.to_le_bytes());
+        bytes.extend_from_slice(&(hidden as u32).to_le_bytes());
+        bytes.resize(bytes.len() + (INPUTS * hidden + 3 * hidden) * 2 + 4, 0);
+        let checksum = fnv1a(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn update_matches_refresh() {
+        let network = random_network(HIDDEN);
+        let positions = [
+            // castling both ways for both sides, lots of captures
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            // exf6 en passant
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            // promotions with and without a capture
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        ];
+        let (mut captures, mut castles, mut en_passants, mut promotions) = (0, 0, 0, 0);
+        for fen in positions {
+            let board = Board::from_str(fen).unwrap();
+            let parent = refreshed(&network, &board);
+            let mut children: Vec<(Option<ChessMove>, Board)> = MoveGen::new_legal(&board).map(|m| (Some(m), board.make_move_new(m))).collect();
+            if let Some(null_board) = board.null_move() {
+                children.push((None, null_board));
+            }
+            for (m, after) in children {
+                let mut child = Accumulator::new(&network);
+                child.update_from(&parent, &network, &board, &after);
+                assert_same(&child, &refreshed(&network, &after), &after);
+
+                if let Some(m) = m {
+                    let moved = board.piece_on(m.get_source());
+                    captures += board.color_on(m.get_dest()).is_some() as u32;
+                    castles += (moved == Some(Piece::King) && m.get_source().get_file().to_index().abs_diff(m.get_dest().get_file().to_index()) == 2) as u32;
+                    en_passants += (moved == Some(Piece::Pawn) && board.piece_on(m.get_dest()).is_none() && m.get_source().get_file() != m.get_dest().get_file()) as u32;
+                    promotions += m.get_promotion().is_some() as u32;
+                }
+
+                // and on from an updated one
+                for grandchild in MoveGen::new_legal(&after).map(|m| after.make_move_new(m)) {
+                    let mut next = Accumulator::new(&network);
+                    next.update_from(&child, &network, &after, &grandchild);
+                    assert_same(&next, &refreshed(&network, &grandchild), &grandchild);
+                }
+            }
+        }
+        // every kind of move really came up
+        assert!(captures > 0 && castles > 0 && en_passants > 0 && promotions > 0);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let network = random_network(HIDDEN);
+        let bytes = network.to_bytes();
+        let loaded = Network::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.hidden_size(), HIDDEN);
+        assert_eq!(loaded.to_bytes(), bytes);
+        let board = Board::default();
+        assert_eq!(loaded.evaluate_board(&board), network.evaluate_board(&board));
+    }
+
+    #[test]
+    fn bad_files_are_rejected() {
+        assert!(Network::from_bytes(&file(VERSION, HIDDEN)).is_ok());
+
+        let bytes = random_network(HIDDEN).to_bytes();
+        assert!(Network::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Network::from_bytes(&bytes[..MAGIC.len() + 4]).is_err());
+        assert!(Network::from_bytes(&[]).is_err());
+
+        let mut damaged = bytes.clone();
+        damaged[MAGIC.len() + 100] ^= 1;
+        assert!(Network::from_bytes(&damaged).err().unwrap().contains("checksum"));
+
+        assert!(Network::from_bytes(&file(VERSION + 1, HIDDEN)).err().unwrap().contains("version"));
+        // the right size and checksum for 20 neurons, but the simd code needs multiples of 16
+        assert!(Network::from_bytes(&file(VERSION, 20)).err().unwrap().contains("multiple"));
+        assert!(Network::from_bytes(&file(VERSION, 0)).is_err());
+    }
+}
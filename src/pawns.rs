@@ -0,0 +1,369 @@
+use chess::{BitBoard, Board, Color, Piece, Square, ALL_SQUARES};
+use crate::attacks::{distance, pawn_attacks, FILE_A};
+use crate::eval::{EvalParams, Sides};
+
+// pawn structure. it only depends on where the pawns are, so the search keeps the scores in a
+// small table keyed on a hash of the pawns and most positions (quiescence search above all,
+// where only pieces get captured) never work them out again. the same goes for which pawns are
+// passed, but what they're worth also depends on the pieces and kings so that part is redone
+// every time
+
+// terms, the index into EvalParams::pawn_structure_mid/_end
+pub const DOUBLED: usize = 0;
+pub const ISOLATED: usize = 1;
+// can't be defended by another pawn and can't safely step up to its neighbours either
+pub const BACKWARD: usize = 2;
+// defended by a pawn
+pub const CONNECTED: usize = 3;
+// next to a pawn on the same rank
+pub const PHALANX: usize = 4;
+// nothing in front on its file and at least as many helpers as guards on the files next to it
+pub const CANDIDATE: usize = 5;
+pub const PAWN_TERMS: usize = 6;
+
+// passed pawn terms, the index into EvalParams::passed_mid/_end. all but the last are
+// multiplied by how far up the pawn is (see passed_weight)
+// nothing on the way to the promotion square
+pub const FREE_PATH: usize = 0;
+// every square on the way is either safe from them or covered by us
+pub const SUPPORTED_PATH: usize = 1;
+// per square between the kings and the square in front of the pawn
+pub const OUR_KING_DISTANCE: usize = 2;
+pub const THEIR_KING_DISTANCE: usize = 3;
+// one of their pieces sits right in front of it
+pub const BLOCKADED: usize = 4;
+// pawn endings only: their king can't catch it (the rule of the square), not scaled
+pub const UNSTOPPABLE: usize = 5;
+pub const PASSED_TERMS: usize = 6;
+
+// zobrist keys for the pawn hash (the chess crate's get_pawn_hash is a stub), made at compile
+// time with splitmix64
+const PAWN_KEYS: [[u64; 64]; 2] = pawn_keys();
+
+const fn pawn_keys() -> [[u64; 64]; 2] {
+    let mut keys = [[0; 64]; 2];
+    let mut state: u64 = 0x5eed;
+    let mut i = 0;
+    while i < 128 {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        keys[i / 64][i % 64] = z ^ (z >> 31);
+        i += 1;
+    }
+    keys
+}
+
+pub fn pawn_hash(board: &Board) -> u64 {
+    let mut hash = 0;
+    for color in [Color::White, Color::Black] {
+        for square in *board.pieces(Piece::Pawn) & *board.color_combined(color) {
+            hash ^= PAWN_KEYS[color.to_index()][square.to_index()];
+        }
+    }
+    hash
+}
+
+pub fn file_mask(file: usize) -> u64 {
+    FILE_A << file
+}
+
+pub fn adjacent_files(file: usize) -> u64 {
+    let left = if file > 0 { file_mask(file - 1) } else { 0 };
+    let right = if file < 7 { file_mask(file + 1) } else { 0 };
+    left | right
+}
+
+// every square on the ranks in front of `square`, seen from `color`
+pub fn ranks_ahead(color: Color, square: Square) -> u64 {
+    let rank = square.get_rank().to_index();
+    match color {
+        Color::White if rank < 7 => !0 << (8 * (rank + 1)),
+        Color::Black => (1 << (8 * rank)) - 1,
+        _ => 0,
+    }
+}
+
+// `color`'s pawns with no pawn of theirs in front or on the files next to it
+fn passed_pawns(color: Color, ours: u64, theirs: u64) -> u64 {
+    let mut passed = 0;
+    for square in BitBoard(ours) {
+        let file = square.get_file().to_index();
+        if theirs & (file_mask(file) | adjacent_files(file)) & ranks_ahead(color, square) == 0 {
+            passed |= 1 << square.to_index();
+        }
+    }
+    passed
+}
+
+// counts of each term for `color`'s pawns
+fn count_terms(color: Color, ours: u64, theirs: u64) -> [i32; PAWN_TERMS] {
+    let mut counts = [0; PAWN_TERMS];
+    let defended = pawn_attacks(color, ours);
+    let guarded = pawn_attacks(!color, theirs);
+    for square in BitBoard(ours) {
+        let bit = 1u64 << square.to_index();
+        let file = square.get_file().to_index();
+        let rank = square.get_rank().to_index();
+        let ahead = ranks_ahead(color, square);
+        let neighbours = adjacent_files(file);
+        // our pawns on the neighbouring files that are level with this one or behind it
+        let behind_or_level = !ahead & !bit;
+        let supporters = ours & neighbours & behind_or_level;
+
+        if ours & file_mask(file) & ahead != 0 {
+            counts[DOUBLED] += 1;
+        }
+        if ours & neighbours == 0 {
+            counts[ISOLATED] += 1;
+        } else if supporters == 0 {
+            let stop = if color == Color::White { bit << 8 } else { bit >> 8 };
+            if guarded & stop != 0 {
+                counts[BACKWARD] += 1;
+            }
+        }
+        if defended & bit != 0 {
+            counts[CONNECTED] += 1;
+        }
+        if ours & neighbours & (0xff << (8 * rank)) != 0 {
+            counts[PHALANX] += 1;
+        }
+        let open = theirs & file_mask(file) & ahead == 0;
+        let guards = theirs & neighbours & ahead;
+        // passed pawns aren't candidates, they're already there
+        if open && guards != 0 && guards.count_ones() <= supporters.count_ones() {
+            counts[CANDIDATE] += 1;
+        }
+    }
+    counts
+}
+
+// what the pawn table keeps
+#[derive(Clone, Copy, Default)]
+pub struct PawnScore {
+    pub structure: Sides,
+    // passed pawns of both sides
+    pub passed: u64,
+}
+
+pub fn evaluate(board: &Board, params: &EvalParams) -> PawnScore {
+    let pawns = board.pieces(Piece::Pawn);
+    let white = (pawns & board.color_combined(Color::White)).0;
+    let black = (pawns & board.color_combined(Color::Black)).0;
+    let mut score = PawnScore {
+        passed: passed_pawns(Color::White, white, black) | passed_pawns(Color::Black, black, white),
+        ..PawnScore::default()
+    };
+    for (side, counts) in [count_terms(Color::White, white, black), count_terms(Color::Black, black, white)].iter().enumerate() {
+        for (term, count) in counts.iter().enumerate() {
+            score.structure[side].0 += count * params.pawn_structure_mid[term];
+            score.structure[side].1 += count * params.pawn_structure_end[term];
+        }
+    }
+    score
+}
+
+#[derive(Clone, Copy, Default)]
+struct PawnEntry {
+    key: u64,
+    score: PawnScore,
+}
+
+// 2^14 entries of 32 bytes (512 kb), plenty since pawn structures repeat a lot in a search
+const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+// one per search thread, it only holds scores for the default weights
+pub struct PawnTable {
+    entries: Vec<PawnEntry>,
+}
+
+impl PawnTable {
+    pub fn new() -> Self {
+        PawnTable {
+            entries: vec![PawnEntry::default(); PAWN_TABLE_SIZE],
+        }
+    }
+
+    // the empty table already knows no pawns means 0, which is right
+    pub fn probe(&mut self, board: &Board, params: &EvalParams) -> PawnScore {
+        let key = pawn_hash(board);
+        let entry = &mut self.entries[key as usize & (PAWN_TABLE_SIZE - 1)];
+        if entry.key != key {
+            *entry = PawnEntry {
+                key,
+                score: evaluate(board, params),
+            };
+        }
+        entry.score
+    }
+}
+
+impl Default for PawnTable {
+    fn default() -> Self {
+        PawnTable::new()
+    }
+}
+
+// 0 for a pawn that hasn't moved up to 5 for one on the seventh
+fn passed_weight(relative_rank: usize) -> i32 {
+    relative_rank.saturating_sub(1) as i32
+}
+
+// the passed pawns out of the pawn table. `attacked` is every square each side attacks, white
+// first
+pub fn evaluate_passed(board: &Board, passed: u64, attacked: &[u64; 2], params: &EvalParams) -> Sides {
+    let mut sides = Sides::default();
+    if passed == 0 {
+        return sides;
+    }
+    let occupied = board.combined().0;
+    for color in [Color::White, Color::Black] {
+        let (mid, end) = &mut sides[color.to_index()];
+        let their_king = board.king_square(!color);
+        let our_king = board.king_square(color);
+        // only kings and pawns left on their side
+        let pawn_ending = board.color_combined(!color) & !board.pieces(Piece::Pawn) == BitBoard::from_square(their_king);
+        for square in BitBoard(passed) & board.color_combined(color) {
+            let file = square.get_file().to_index();
+            let rank = square.get_rank().to_index();
+            let relative_rank = if color == Color::White { rank } else { 7 - rank };
+            let weight = passed_weight(relative_rank);
+            let path = file_mask(file) & ranks_ahead(color, square);
+            let stop_index = if color == Color::White { square.to_index() + 8 } else { square.to_index() - 8 };
+            let stop = ALL_SQUARES[stop_index];
+            let promotion = ALL_SQUARES[if color == Color::White { 56 + file } else { file }];
+
+            let mut terms = [0; PASSED_TERMS];
+            if path & occupied == 0 {
+                terms[FREE_PATH] = weight;
+            }
+            let unsafe_squares = path & attacked[(!color).to_index()];
+            if unsafe_squares & !attacked[color.to_index()] == 0 {
+                terms[SUPPORTED_PATH] = weight;
+            }
+            terms[OUR_KING_DISTANCE] = weight * distance(our_king, stop);
+            terms[THEIR_KING_DISTANCE] = weight * distance(their_king, stop);
+            if board.color_on(stop) == Some(!color) {
+                terms[BLOCKADED] = weight;
+            }
+            if pawn_ending && path & board.color_combined(color).0 == 0 {
+                // a pawn on its starting rank can still move two squares
+                let pawn_moves = (7 - relative_rank).min(5) as i32;
+                let their_moves = distance(their_king, promotion) - (board.side_to_move() != color) as i32;
+                if pawn_moves < their_moves {
+                    terms[UNSTOPPABLE] = 1;
+                }
+            }
+
+            *mid += params.passed_rank_mid[relative_rank];
+            *end += params.passed_rank_end[relative_rank];
+            for (term, count) in terms.iter().enumerate() {
+                *mid += count * params.passed_mid[term];
+                *end += count * params.passed_end[term];
+            }
+        }
+    }
+    sides
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::DEFAULT_PARAMS;
+    use std::str::FromStr;
+
+    fn bits(squares: &[&str]) -> u64 {
+        squares.iter().fold(0, |bits, square| bits | 1 << Square::from_str(square).unwrap().to_index())
+    }
+
+    // white's counts, and black's for the same pawns upside down (swap_bytes flips the ranks)
+    fn counts(white: &[&str], black: &[&str]) -> [i32; PAWN_TERMS] {
+        let (white, black) = (bits(white), bits(black));
+        let counts = count_terms(Color::White, white, black);
+        assert_eq!(count_terms(Color::Black, white.swap_bytes(), black.swap_bytes()), counts);
+        counts
+    }
+
+    #[test]
+    fn structure_terms() {
+        // both a-pawns are isolated, the back one is doubled
+        assert_eq!(counts(&["a2", "a4"], &[]), [1, 2, 0, 0, 0, 0]);
+        // d3 has no pawn level with or behind it and c5 guards d4, e4 is defended by it
+        assert_eq!(counts(&["d3", "e4"], &["c5"]), [0, 0, 1, 1, 0, 0]);
+        // with d4 free it's just behind, not backward
+        assert_eq!(counts(&["d3", "e4"], &["h7"])[BACKWARD], 0);
+        assert_eq!(counts(&["d4", "e4"], &[]), [0, 0, 0, 0, 2, 0]);
+        // b5 has a free file and one helper (c4 behind it) for the one guard on a6
+        assert_eq!(counts(&["b5", "c4"], &["a6"])[CANDIDATE], 1);
+        assert_eq!(counts(&["b5", "c4"], &["a6", "c6"])[CANDIDATE], 0);
+    }
+
+    #[test]
+    fn passed() {
+        assert_eq!(passed_pawns(Color::White, bits(&["a5", "e4"]), bits(&["c6", "e6"])), bits(&["a5"]));
+        assert_eq!(passed_pawns(Color::White, bits(&["a5"]), bits(&["b6"])), 0);
+        // behind the pawn doesn't count
+        assert_eq!(passed_pawns(Color::White, bits(&["a5"]), bits(&["b4"])), bits(&["a5"]));
+        assert_eq!(passed_pawns(Color::Black, bits(&["h3"]), bits(&["g2"])), 0);
+        assert_eq!(passed_pawns(Color::Black, bits(&["h3"]), bits(&["g4"])), bits(&["h3"]));
+    }
+
+    #[test]
+    fn scores_go_to_the_right_side() {
+        let mut params = EvalParams::from_vec(&vec![0; DEFAULT_PARAMS.to_vec().len()]);
+        params.pawn_structure_mid[DOUBLED] = -10;
+        params.pawn_structure_end[ISOLATED] = -20;
+        let board = Board::from_str("4k3/8/8/8/P7/8/P7/4K3 w - - 0 1").unwrap();
+        assert_eq!(evaluate(&board, &params).structure, [(-10, -40), (0, 0)]);
+        let board = Board::from_str("4k3/7p/8/7p/8/8/8/4K3 w - - 0 1").unwrap();
+        let score = evaluate(&board, &params);
+        assert_eq!(score.structure, [(0, 0), (-10, -40)]);
+        // passed only asks about their pawns, so both are
+        assert_eq!(score.passed, bits(&["h5", "h7"]));
+    }
+
+    // the passed pawn score of both sides with only `set` weights switched on
+    fn passed_score(fen: &str, set: impl FnOnce(&mut EvalParams)) -> Sides {
+        let mut params = EvalParams::from_vec(&vec![0; DEFAULT_PARAMS.to_vec().len()]);
+        set(&mut params);
+        let board = Board::from_str(fen).unwrap();
+        let attacked = [crate::attacks::attacks(&board, Color::White), crate::attacks::attacks(&board, Color::Black)];
+        evaluate_passed(&board, evaluate(&board, &params).passed, &attacked, &params)
+    }
+
+    #[test]
+    fn rule_of_the_square() {
+        let unstoppable = |fen| passed_score(fen, |params| params.passed_end[UNSTOPPABLE] = 100);
+        // the king on e6 is outside the a5 pawn's square, unless it's its move
+        assert_eq!(unstoppable("8/8/4k3/P7/8/8/8/4K3 w - - 0 1"), [(0, 100), (0, 0)]);
+        assert_eq!(unstoppable("8/8/4k3/P7/8/8/8/4K3 b - - 0 1"), [(0, 0), (0, 0)]);
+        assert_eq!(unstoppable("8/8/3k4/P7/8/8/8/4K3 w - - 0 1"), [(0, 0), (0, 0)]);
+        // the same for black, its pawn on h4 runs to h1
+        assert_eq!(unstoppable("4k3/8/8/8/7p/3K4/8/8 b - - 0 1"), [(0, 0), (0, 100)]);
+        assert_eq!(unstoppable("4k3/8/8/8/7p/3K4/8/8 w - - 0 1"), [(0, 0), (0, 0)]);
+        // a pawn on its starting rank gets to jump a square
+        assert_eq!(unstoppable("8/8/8/8/8/6k1/P7/4K3 w - - 0 1"), [(0, 100), (0, 0)]);
+        assert_eq!(unstoppable("8/8/8/8/8/5k2/P7/4K3 w - - 0 1"), [(0, 0), (0, 0)]);
+        // only in pawn endings, a piece can always come back
+        assert_eq!(unstoppable("8/8/4k3/P7/8/8/8/4K1n1 w - - 0 1"), [(0, 0), (0, 0)]);
+    }
+
+    #[test]
+    fn passed_pawn_terms() {
+        // a5 is on its fifth rank, weight 3. nothing on a6 to a8 and their king is 4 from a6
+        let score = passed_score("8/8/4k3/P7/8/8/8/4K3 b - - 0 1", |params| {
+            params.passed_rank_end[4] = 1000;
+            params.passed_end[FREE_PATH] = 1;
+            params.passed_end[THEIR_KING_DISTANCE] = 100;
+        });
+        assert_eq!(score, [(0, 1000 + 3 + 3 * 4 * 100), (0, 0)]);
+        // blockaded by a knight on a6, which also ends the free path
+        let blockaded = |params: &mut EvalParams| {
+            params.passed_mid[BLOCKADED] = -1;
+            params.passed_mid[FREE_PATH] = 10;
+        };
+        assert_eq!(passed_score("8/8/n3k3/P7/8/8/8/4K3 w - - 0 1", blockaded), [(-3, 0), (0, 0)]);
+        assert_eq!(passed_score("8/8/4k3/P7/8/8/8/4K3 w - - 0 1", blockaded), [(30, 0), (0, 0)]);
+    }
+}
@@ -0,0 +1,71 @@
+use chess::{Board, ChessMove, MoveGen};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// perft: count every leaf of the legal move tree to a fixed depth and compare with the known
+// numbers. a wrong count means move generation or make_move is broken somewhere
+
+// the last ply isn't played out, the number of legal moves there is all we need (bulk counting)
+pub fn perft(board: &Board, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = MoveGen::new_legal(board);
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    moves.map(|m| perft(&board.make_move_new(m), depth - 1)).sum()
+}
+
+// perft split up by root move, to find which move a wrong count comes from
+pub fn divide(board: &Board, depth: u32) -> Vec<(ChessMove, u64)> {
+    MoveGen::new_legal(board)
+        .map(|m| (m, perft(&board.make_move_new(m), depth.saturating_sub(1))))
+        .collect()
+}
+
+pub fn print_perft(board: &Board, depth: u32) {
+    let timer = Instant::now();
+    let nodes = perft(board, depth);
+    print_summary(nodes, timer.elapsed());
+}
+
+pub fn print_divide(board: &Board, depth: u32) {
+    let timer = Instant::now();
+    let mut nodes = 0;
+    for (m, count) in divide(board, depth) {
+        println!("{}: {}", m, count);
+        nodes += count;
+    }
+    print_summary(nodes, timer.elapsed());
+}
+
+fn print_summary(nodes: u64, elapsed: Duration) {
+    let millis = elapsed.as_millis() as u64;
+    let nps = (nodes * 1000).checked_div(millis).unwrap_or(nodes);
+    println!("nodes {} time {} ms nps {}", nodes, millis, nps);
+}
+
+// `perft <depth> [fen]` and `divide <depth> [fen]` from the command line, startpos without a fen
+pub fn run(command: &str, args: &[String]) {
+    let Some(depth) = args.first().and_then(|d| d.parse().ok()) else {
+        println!("usage: {} <depth> [fen]", command);
+        return;
+    };
+    let board = if args.len() > 1 {
+        match Board::from_str(&args[1..].join(" ")) {
+            Ok(board) => board,
+            Err(_) => {
+                println!("invalid fen: {}", args[1..].join(" "));
+                return;
+            }
+        }
+    } else {
+        Board::default()
+    };
+    if command == "divide" {
+        print_divide(&board, depth);
+    } else {
+        print_perft(&board, depth);
+    }
+}
@@ -0,0 +1,188 @@
+use chess::{Board, BoardStatus, ChessMove, MoveGen, Piece};
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// standard algebraic notation for a legal move, the chess crate can only read it
+pub fn san(board: &Board, m: ChessMove) -> String {
+    let source = m.get_source();
+    let dest = m.get_dest();
+    let piece = board.piece_on(source).expect("a legal move starts on a piece");
+    let mut text = String::new();
+    if piece == Piece::King && source.get_file().to_index().abs_diff(dest.get_file().to_index()) == 2 {
+        text.push_str(if dest.get_file() > source.get_file() { "O-O" } else { "O-O-O" });
+    } else {
+        let is_capture = board.piece_on(dest).is_some() || (piece == Piece::Pawn && source.get_file() != dest.get_file());
+        if piece == Piece::Pawn {
+            if is_capture {
+                text.push(file_char(source.get_file().to_index()));
+            }
+        } else {
+            text.push(piece_char(piece));
+            // other pieces of the same kind that could go to the same square
+            let rivals: Vec<ChessMove> = MoveGen::new_legal(board)
+                .filter(|other| other.get_dest() == dest && other.get_source() != source && board.piece_on(other.get_source()) == Some(piece))
+                .collect();
+            if !rivals.is_empty() {
+                let same_file = rivals.iter().any(|other| other.get_source().get_file() == source.get_file());
+                let same_rank = rivals.iter().any(|other| other.get_source().get_rank() == source.get_rank());
+                if !same_file {
+                    text.push(file_char(source.get_file().to_index()));
+                } else if !same_rank {
+                    text.push(rank_char(source.get_rank().to_index()));
+                } else {
+                    text.push(file_char(source.get_file().to_index()));
+                    text.push(rank_char(source.get_rank().to_index()));
+                }
+            }
+        }
+        if is_capture {
+            text.push('x');
+        }
+        text.push(file_char(dest.get_file().to_index()));
+        text.push(rank_char(dest.get_rank().to_index()));
+        if let Some(promotion) = m.get_promotion() {
+            text.push('=');
+            text.push(piece_char(promotion));
+        }
+    }
+    let after = board.make_move_new(m);
+    if after.status() == BoardStatus::Checkmate {
+        text.push('#');
+    } else if after.checkers().popcnt() > 0 {
+        text.push('+');
+    }
+    text
+}
+
+fn piece_char(piece: Piece) -> char {
+    match piece {
+        Piece::Pawn => 'P',
+        Piece::Knight => 'N',
+        Piece::Bishop => 'B',
+        Piece::Rook => 'R',
+        Piece::Queen => 'Q',
+        Piece::King => 'K',
+    }
+}
+
+fn file_char(file: usize) -> char {
+    (b'a' + file as u8) as char
+}
+
+fn rank_char(rank: usize) -> char {
+    (b'1' + rank as u8) as char
+}
+
+// one game in pgn. `tags` go after the seven required ones (minus Result, which is `result`).
+// the moves are numbered from 1, which matches the fen we write for a non standard start
+pub fn write_game(out: &mut impl Write, tags: &[(&str, String)], start: &Board, moves: &[ChessMove], result: &str) -> io::Result<()> {
+    for (name, value) in tags {
+        writeln!(out, "[{} \"{}\"]", name, value.replace('"', "'"))?;
+    }
+    writeln!(out, "[Result \"{}\"]", result)?;
+    if *start != Board::default() {
+        writeln!(out, "[SetUp \"1\"]")?;
+        writeln!(out, "[FEN \"{}\"]", start)?;
+    }
+    writeln!(out, "[PlyCount \"{}\"]", moves.len())?;
+    writeln!(out)?;
+
+    let mut tokens = Vec::new();
+    let mut board = *start;
+    let black_first = start.side_to_move() == chess::Color::Black;
+    for (ply, m) in moves.iter().enumerate() {
+        let ply = ply + black_first as usize;
+        if ply.is_multiple_of(2) {
+            tokens.push(format!("{}.", ply / 2 + 1));
+        } else if tokens.is_empty() {
+            tokens.push(format!("{}...", ply / 2 + 1));
+        }
+        tokens.push(san(&board, *m));
+        board = board.make_move_new(*m);
+    }
+    tokens.push(result.to_string());
+
+    // movetext lines stay under 80 characters
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > 79 {
+            writeln!(out, "{}", line)?;
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    writeln!(out, "{}", line)?;
+    writeln!(out)
+}
+
+// today as yyyy.mm.dd for the Date tag
+pub fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() / 86400) as i64;
+    // days since 1970-01-01 to a civil date (howard hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn san_of(fen: &str, uci: &str) -> String {
+        let board = Board::from_str(fen).unwrap();
+        let m = MoveGen::new_legal(&board).find(|m| m.to_string() == uci).expect("a legal move");
+        san(&board, m)
+    }
+
+    #[test]
+    fn plain_moves() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(san_of(start, "e2e4"), "e4");
+        assert_eq!(san_of(start, "g1f3"), "Nf3");
+        assert_eq!(san_of("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", "e5f7"), "Nxf7");
+        assert_eq!(san_of("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3", "e5f6"), "exf6");
+    }
+
+    #[test]
+    fn castling() {
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(san_of(kiwipete, "e1g1"), "O-O");
+        assert_eq!(san_of(kiwipete, "e1c1"), "O-O-O");
+        assert_eq!(san_of("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "e8c8"), "O-O-O");
+    }
+
+    #[test]
+    fn disambiguation() {
+        // by file, by rank, by both
+        assert_eq!(san_of("4k3/8/8/8/8/8/8/R4RK1 w - - 0 1", "a1d1"), "Rad1");
+        assert_eq!(san_of("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "a1a3"), "R1a3");
+        assert_eq!(san_of("4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1", "a1b2"), "Qa1b2");
+        // a pinned rival can't go there, so there's nothing to tell apart
+        assert_eq!(san_of("4k3/8/8/8/8/8/8/2N1K1Nr w - - 0 1", "c1e2"), "Ne2");
+    }
+
+    #[test]
+    fn promotions() {
+        assert_eq!(san_of("8/P3k3/8/8/8/8/8/4K3 w - - 0 1", "a7a8q"), "a8=Q");
+        assert_eq!(san_of("1n6/P3k3/8/8/8/8/8/4K3 w - - 0 1", "a7b8n"), "axb8=N");
+        assert_eq!(san_of("4k3/8/8/8/8/8/p7/4K3 b - - 0 1", "a2a1r"), "a1=R+");
+    }
+
+    #[test]
+    fn check_and_mate() {
+        assert_eq!(san_of("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", "a1a8"), "Ra8+");
+        assert_eq!(san_of("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "a1a8"), "Ra8#");
+        assert_eq!(san_of("r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4", "h5f7"), "Qxf7#");
+    }
+}
@@ -0,0 +1,287 @@
+use chess::{Board, BoardStatus, ChessMove, Color, MoveGen, Piece};
+use crate::engine::{Engine, SearchLimits, SearchSignals, MATE_SCORE, MAX_PLY};
+use crate::match_runner::{game_over, load_openings};
+use crate::rng::Rng;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+// self-play for training data: fast games from random or book openings, every quiet position
+// the engine searched goes into a text file, one per line:
+//
+//   <fen> | <score> | <best move> | <ply> | <result>
+//
+// score is the search score in centipawns from white's point of view, ply counts from where the
+// engine took over from the opening, result is 1.0, 0.5 or 0.0 for white
+
+const USAGE: &str = "usage: selfplay [-games N] [-nodes N | -depth N] [-concurrency N] [-randomplies N] \
+[-openings file=FILE] [-seed N] [-out FILE]";
+
+// games are decided once one side is this far ahead for a few plies in a row
+const RESIGN_SCORE: i32 = 1500;
+const RESIGN_PLIES: u32 = 4;
+const MAX_GAME_PLIES: usize = 400;
+// random openings tried before giving up on a game
+const OPENING_TRIES: usize = 1000;
+
+struct Settings {
+    games: usize,
+    limits: SearchLimits,
+    concurrency: usize,
+    // random moves played from the opening before the engine takes over
+    random_plies: usize,
+    openings: Vec<Board>,
+    seed: u64,
+    out: String,
+}
+
+// a quiet position with what the search made of it
+struct Sample {
+    board: Board,
+    // white's point of view
+    score: i32,
+    best_move: ChessMove,
+    ply: usize,
+}
+
+// a line of the output read back in, for whoever trains on it
+pub struct Record {
+    pub board: Board,
+    // white's point of view
+    pub score: i32,
+    pub result: f32,
+}
+
+pub fn parse_record(line: &str) -> Option<Record> {
+    let fields: Vec<&str> = line.split('|').map(str::trim).collect();
+    if fields.len() != 5 {
+        return None;
+    }
+    Some(Record {
+        board: Board::from_str(fields[0]).ok()?,
+        score: fields[1].parse().ok()?,
+        result: fields[4].parse().ok()?,
+    })
+}
+
+// positions from one game and how it ended, 1.0 / 0.5 / 0.0 for white
+struct GameData {
+    samples: Vec<Sample>,
+    result: f32,
+}
+
+// quiet: nothing hanging in the search's eyes, no check, no forced mate. the eval can be fitted
+// to the score of these without a tactical swing in the way
+fn is_quiet(board: &Board, best_move: ChessMove, score: i32) -> bool {
+    let is_capture = board.piece_on(best_move.get_dest()).is_some()
+        || (board.piece_on(best_move.get_source()) == Some(Piece::Pawn) && best_move.get_source().get_file() != best_move.get_dest().get_file());
+    board.checkers().popcnt() == 0 && !is_capture && best_move.get_promotion().is_none() && score.abs() < MATE_SCORE - MAX_PLY as i32
+}
+
+// the opening plus a few random legal moves, starting over if the random moves end the game.
+// gives up after OPENING_TRIES goes, the book is no good then
+fn random_opening(openings: &[Board], random_plies: usize, rng: &mut Rng) -> Result<Board, String> {
+    for _ in 0..OPENING_TRIES {
+        let mut board = openings[rng.below(openings.len())];
+        for _ in 0..random_plies {
+            let moves: Vec<ChessMove> = MoveGen::new_legal(&board).collect();
+            if moves.is_empty() {
+                break;
+            }
+            board = board.make_move_new(moves[rng.below(moves.len())]);
+        }
+        if MoveGen::new_legal(&board).len() > 0 {
+            return Ok(board);
+        }
+    }
+    Err(format!("no playable opening after {} tries, every random line ended the game", OPENING_TRIES))
+}
+
+fn play_game(engine: &Engine, start: Board, limits: &SearchLimits) -> GameData {
+    engine.new_game();
+    let mut board = start;
+    let mut samples = Vec::new();
+    let mut hashes = vec![board.get_hash()];
+    let mut halfmoves = 0;
+    let mut winning_plies = 0;
+    let mut last_winner = None;
+    for ply in 0..MAX_GAME_PLIES {
+        if let Some((result, _)) = game_over(&board, &hashes, halfmoves) {
+            let result = match result {
+                "1-0" => 1.0,
+                "0-1" => 0.0,
+                _ => 0.5,
+            };
+            return GameData { samples, result };
+        }
+        let result = engine.search(board, limits, &SearchSignals::default(), |_| {});
+        let Some(best_move) = result.best_move else {
+            break;
+        };
+        let white_score = if board.side_to_move() == Color::White { result.score } else { -result.score };
+        if is_quiet(&board, best_move, result.score) {
+            samples.push(Sample {
+                board,
+                score: white_score,
+                best_move,
+                ply,
+            });
+        }
+
+        // one side has been winning for long enough, call it
+        let winner = if white_score >= RESIGN_SCORE {
+            Some(Color::White)
+        } else if white_score <= -RESIGN_SCORE {
+            Some(Color::Black)
+        } else {
+            None
+        };
+        winning_plies = match winner {
+            Some(_) if winner == last_winner => winning_plies + 1,
+            Some(_) => 1,
+            None => 0,
+        };
+        last_winner = winner;
+        if let Some(winner) = winner
+            && winning_plies >= RESIGN_PLIES {
+            let result = if winner == Color::White { 1.0 } else { 0.0 };
+            return GameData { samples, result };
+        }
+
+        let irreversible = board.piece_on(best_move.get_source()) == Some(Piece::Pawn) || board.piece_on(best_move.get_dest()).is_some();
+        board = board.make_move_new(best_move);
+        if irreversible {
+            halfmoves = 0;
+            hashes.clear();
+        } else {
+            halfmoves += 1;
+        }
+        hashes.push(board.get_hash());
+    }
+    // too long, call it a draw
+    GameData { samples, result: 0.5 }
+}
+
+pub fn run(args: &[String]) {
+    let settings = match parse_args(args) {
+        Ok(settings) => settings,
+        Err(e) => {
+            println!("{}", e);
+            println!("{}", USAGE);
+            return;
+        }
+    };
+    let mut out = match File::create(&settings.out) {
+        Ok(file) => BufWriter::new(file),
+        Err(e) => {
+            println!("can't write {}: {}", settings.out, e);
+            return;
+        }
+    };
+
+    let next_game = AtomicUsize::new(0);
+    let mut seen = HashSet::new();
+    let (mut games, mut written, mut duplicates) = (0usize, 0, 0);
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..settings.concurrency {
+            let sender = sender.clone();
+            let (settings, next_game) = (&settings, &next_game);
+            scope.spawn(move || {
+                let engine = Engine::new();
+                loop {
+                    let game = next_game.fetch_add(1, Ordering::Relaxed);
+                    if game >= settings.games {
+                        break;
+                    }
+                    // seeded per game, so a run gives the same openings however many threads it uses
+                    let mut rng = Rng::new(settings.seed ^ (game as u64).wrapping_mul(0x9e3779b97f4a7c15));
+                    let start = match random_opening(&settings.openings, settings.random_plies, &mut rng) {
+                        Ok(start) => start,
+                        Err(e) => {
+                            println!("{}", e);
+                            break;
+                        }
+                    };
+                    if sender.send(play_game(&engine, start, &settings.limits)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for data in receiver {
+            games += 1;
+            for sample in data.samples {
+                // each position only once per run
+                if !seen.insert(sample.board.get_hash()) {
+                    duplicates += 1;
+                    continue;
+                }
+                let line = format!("{} | {} | {} | {} | {:.1}", sample.board, sample.score, sample.best_move, sample.ply, data.result);
+                if let Err(e) = writeln!(out, "{}", line) {
+                    println!("can't write {}: {}", settings.out, e);
+                    return;
+                }
+                written += 1;
+            }
+            if games.is_multiple_of(10) || games == settings.games {
+                println!("games {}/{} positions {} duplicates {}", games, settings.games, written, duplicates);
+            }
+        }
+    });
+    if let Err(e) = out.flush() {
+        println!("can't write {}: {}", settings.out, e);
+    }
+    println!("wrote {} positions from {} games to {}", written, games, settings.out);
+}
+
+fn parse_args(args: &[String]) -> Result<Settings, String> {
+    let mut settings = Settings {
+        games: 1000,
+        limits: SearchLimits {
+            nodes: Some(5000),
+            ..SearchLimits::default()
+        },
+        concurrency: 1,
+        random_plies: 8,
+        openings: vec![Board::default()],
+        seed: 1,
+        out: "selfplay.txt".to_string(),
+    };
+    for pair in args.chunks(2) {
+        let flag = pair[0].as_str();
+        let value = pair.get(1).ok_or_else(|| format!("{} needs a value", flag))?;
+        let number = || value.parse::<u64>().map_err(|_| format!("not a number: {}", value));
+        match flag {
+            "-games" => settings.games = number()? as usize,
+            "-nodes" => {
+                settings.limits.nodes = Some(number()?);
+                settings.limits.depth = None;
+            }
+            "-depth" => {
+                settings.limits.depth = Some(number()? as u32);
+                settings.limits.nodes = None;
+            }
+            "-concurrency" => settings.concurrency = (number()? as usize).max(1),
+            "-randomplies" => settings.random_plies = number()? as usize,
+            "-seed" => settings.seed = number()?,
+            "-out" => settings.out = value.clone(),
+            "-openings" => {
+                let path = value.strip_prefix("file=").unwrap_or(value);
+                // a mated or stalemated position has no game left to play
+                settings.openings = load_openings(path)?.into_iter().filter(|board| board.status() == BoardStatus::Ongoing).collect();
+                if settings.openings.is_empty() {
+                    return Err(format!("every opening in {} is already over", path));
+                }
+            }
+            _ => return Err(format!("unknown flag {}", flag)),
+        }
+    }
+    Ok(settings)
+}
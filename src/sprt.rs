@@ -0,0 +1,172 @@
+// match statistics: elo with error bars and the sequential probability ratio test used to decide
+// whether a patch gains (H1: elo >= elo1) or not (H0: elo <= elo0) with as few games as possible
+
+// wins, draws and losses of the first engine
+#[derive(Clone, Copy, Default)]
+pub struct Wdl {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl Wdl {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    // average points per game, 0 to 1
+    pub fn score(&self) -> f64 {
+        if self.games() == 0 {
+            return 0.5;
+        }
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    // per game variance of the score
+    fn variance(&self) -> f64 {
+        if self.games() == 0 {
+            return 0.0;
+        }
+        let score = self.score();
+        let games = self.games() as f64;
+        (self.wins as f64 * (1.0 - score).powi(2) + self.draws as f64 * (0.5 - score).powi(2) + self.losses as f64 * score.powi(2)) / games
+    }
+
+    // elo difference and the half width of its 95% confidence interval
+    pub fn elo(&self) -> (f64, f64) {
+        if self.games() == 0 {
+            return (0.0, 0.0);
+        }
+        let score = self.score();
+        let margin = 1.96 * (self.variance() / self.games() as f64).sqrt();
+        let error = (score_to_elo(score + margin) - score_to_elo(score - margin)) / 2.0;
+        (score_to_elo(score), error)
+    }
+}
+
+// logistic elo, clamped so 100% and 0% don't turn into infinities
+pub fn score_to_elo(score: f64) -> f64 {
+    let score = score.clamp(0.001, 0.999);
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+pub fn elo_to_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+#[derive(Clone, Copy)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    // false positive and false negative rates
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SprtResult {
+    Continue,
+    AcceptH0,
+    AcceptH1,
+}
+
+impl Sprt {
+    // log likelihood ratio of H1 against H0, using the normal approximation of the
+    // generalized sprt (the scores of the games are treated as normally distributed)
+    pub fn llr(&self, wdl: &Wdl) -> f64 {
+        let variance = wdl.variance();
+        if wdl.games() == 0 || variance == 0.0 {
+            return 0.0;
+        }
+        let (s0, s1) = (elo_to_score(self.elo0), elo_to_score(self.elo1));
+        wdl.games() as f64 * (s1 - s0) * (2.0 * wdl.score() - s0 - s1) / (2.0 * variance)
+    }
+
+    // (lower, upper): below lower H0 is accepted, above upper H1
+    pub fn bounds(&self) -> (f64, f64) {
+        ((self.beta / (1.0 - self.alpha)).ln(), ((1.0 - self.beta) / self.alpha).ln())
+    }
+
+    pub fn test(&self, wdl: &Wdl) -> SprtResult {
+        let llr = self.llr(wdl);
+        let (lower, upper) = self.bounds();
+        if llr >= upper {
+            SprtResult::AcceptH1
+        } else if llr <= lower {
+            SprtResult::AcceptH0
+        } else {
+            SprtResult::Continue
+        }
+    }
+}
+
+impl Default for Sprt {
+    fn default() -> Self {
+        Sprt {
+            elo0: 0.0,
+            elo1: 5.0,
+            alpha: 0.05,
+            beta: 0.05,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the expected values are worked out by hand from the formulas
+    fn assert_close(value: f64, expected: f64) {
+        assert!((value - expected).abs() < 1e-6, "{} != {}", value, expected);
+    }
+
+    fn wdl(wins: u32, draws: u32, losses: u32) -> Wdl {
+        Wdl { wins, draws, losses }
+    }
+
+    #[test]
+    fn elo_conversions() {
+        assert_close(score_to_elo(0.5), 0.0);
+        assert_close(score_to_elo(0.75), 190.848501887865);
+        assert_close(score_to_elo(0.25), -190.848501887865);
+        assert_close(elo_to_score(0.0), 0.5);
+        assert_close(elo_to_score(100.0), 0.6400649998028851);
+        assert_close(elo_to_score(score_to_elo(0.3)), 0.3);
+        // clamped instead of infinite
+        assert!(score_to_elo(1.0).is_finite() && score_to_elo(0.0).is_finite());
+    }
+
+    #[test]
+    fn elo_with_error_bars() {
+        assert_eq!(Wdl::default().elo(), (0.0, 0.0));
+        let (elo, error) = wdl(60, 20, 20).elo();
+        assert_close(elo, 147.19071411783776);
+        assert_close(error, 66.01463862816014);
+        let (elo, error) = wdl(10, 0, 10).elo();
+        assert_close(elo, 0.0);
+        assert!(error > 0.0);
+    }
+
+    #[test]
+    fn llr_and_bounds() {
+        let sprt = Sprt::default();
+        let (lower, upper) = sprt.bounds();
+        assert_close(lower, -2.9444389791664403);
+        assert_close(upper, 2.9444389791664403);
+
+        assert_close(sprt.llr(&wdl(60, 20, 20)), 0.8832073383814437);
+        assert_close(sprt.llr(&wdl(1000, 2000, 1000)), -0.8283072127988083);
+        // nothing to go on
+        assert_eq!(sprt.llr(&Wdl::default()), 0.0);
+        assert_eq!(sprt.llr(&wdl(0, 10, 0)), 0.0);
+    }
+
+    #[test]
+    fn sprt_decisions() {
+        let sprt = Sprt::default();
+        assert!(sprt.test(&wdl(60, 20, 20)) == SprtResult::Continue);
+        // the llr grows with the games at the same score
+        assert!(sprt.test(&wdl(600, 200, 200)) == SprtResult::AcceptH1);
+        assert!(sprt.test(&wdl(10000, 20000, 10000)) == SprtResult::AcceptH0);
+    }
+}
@@ -0,0 +1,244 @@
+use chess::ChessMove;
+use std::time::Duration;
+
+// what the gui tells us about our own clock
+#[derive(Clone, Copy, Default)]
+pub struct Clock {
+    pub time_left: Duration,
+    pub increment: Duration,
+    // moves until the next time control, None for sudden death / fischer
+    pub moves_to_go: Option<u32>,
+}
+
+// how many moves we budget for when the gui doesn't say (sudden death and fischer)
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+
+// turns clocks into a soft limit (don't start another iteration after this) and a hard limit
+// (abort the search right now). the soft limit gets stretched while the search is unsure
+// and shrunk while it keeps agreeing with itself
+pub struct TimeManager {
+    soft: Option<Duration>,
+    hard: Option<Duration>,
+    // fixed move times are never stretched or cut
+    adaptive: bool,
+    scale: f64,
+    last_best_move: Option<ChessMove>,
+    last_score: Option<i32>,
+    stable_iterations: u32,
+}
+
+impl TimeManager {
+    // no limits at all, the search only stops on depth/nodes (or when told to)
+    pub fn unlimited() -> Self {
+        TimeManager {
+            soft: None,
+            hard: None,
+            adaptive: false,
+            scale: 1.0,
+            last_best_move: None,
+            last_score: None,
+            stable_iterations: 0,
+        }
+    }
+
+    // a fixed time per move, no point stretching or cutting it. the overhead comes off it
+    // like off a clock, the gui counts the time the move takes to reach it
+    pub fn fixed(movetime: Duration, move_overhead: Duration) -> Self {
+        let movetime = movetime.saturating_sub(move_overhead);
+        TimeManager {
+            soft: Some(movetime),
+            hard: Some(movetime),
+            ..TimeManager::unlimited()
+        }
+    }
+
+    // both limits stay within what's really left after the move overhead. on an empty clock
+    // they're 0 and we play whatever depth 1 found, the search can't stop before that
+    pub fn from_clock(clock: &Clock, move_overhead: Duration, legal_moves: usize) -> Self {
+        let available = clock.time_left.saturating_sub(move_overhead);
+        let moves_to_go = clock.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).clamp(1, 50);
+
+        // the base share of what's left, plus most of the increment we get back anyway
+        let mut soft = available / moves_to_go + clock.increment * 3 / 4;
+        // with one move left in the session we can spend nearly everything,
+        // otherwise never sink more than a fraction of the clock into one move
+        let hard = if moves_to_go == 1 {
+            available.mul_f64(0.8)
+        } else {
+            (soft * 4).min(available.mul_f64(0.4))
+        };
+        soft = soft.min(hard);
+
+        // only one legal move, play it as soon as we have something to say about it
+        if legal_moves == 1 {
+            soft = soft.min(Duration::from_millis(10));
+        }
+
+        TimeManager {
+            soft: Some(soft),
+            hard: Some(hard),
+            adaptive: true,
+            ..TimeManager::unlimited()
+        }
+    }
+
+    pub fn hard_limit(&self) -> Option<Duration> {
+        self.hard
+    }
+
+    // called after every completed iteration with its best move and score
+    pub fn update(&mut self, best_move: Option<ChessMove>, score: i32) {
+        if !self.adaptive {
+            return;
+        }
+        if self.last_best_move.is_some() && best_move != self.last_best_move {
+            // the search changed its mind, give it more time to settle
+            self.scale = (self.scale * 1.4).min(2.5);
+            self.stable_iterations = 0;
+        } else {
+            self.stable_iterations += 1;
+            if self.stable_iterations >= 4 {
+                self.scale = (self.scale * 0.9).max(0.5);
+            }
+        }
+        // the score is sinking, keep looking for something better
+        if let Some(last_score) = self.last_score
+            && score < last_score - 30 {
+            self.scale = (self.scale * 1.2).min(2.5);
+        }
+        self.last_best_move = best_move;
+        self.last_score = Some(score);
+    }
+
+    // whether it is worth starting another iteration
+    pub fn soft_limit_reached(&self, elapsed: Duration) -> bool {
+        let Some(soft) = self.soft else {
+            return false;
+        };
+        let soft = soft.mul_f64(self.scale);
+        let soft = match self.hard {
+            Some(hard) => soft.min(hard),
+            None => soft,
+        };
+        elapsed >= soft
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chess::{Square, ALL_SQUARES};
+
+    const MS: Duration = Duration::from_millis(1);
+
+    fn clock(time_left: u64, increment: u64, moves_to_go: Option<u32>) -> Clock {
+        Clock {
+            time_left: Duration::from_millis(time_left),
+            increment: Duration::from_millis(increment),
+            moves_to_go,
+        }
+    }
+
+    fn limits(time: &TimeManager) -> (Duration, Duration) {
+        (time.soft.unwrap(), time.hard.unwrap())
+    }
+
+    #[test]
+    fn clock_limits() {
+        // sudden death: a 30th of the clock, at most 4 times that or 40% of the clock
+        let time = TimeManager::from_clock(&clock(60_000, 0, None), Duration::ZERO, 20);
+        assert_eq!(limits(&time), (MS * 2000, MS * 8000));
+        // fischer: plus three quarters of the increment
+        let time = TimeManager::from_clock(&clock(60_000, 1000, None), Duration::ZERO, 20);
+        assert_eq!(limits(&time), (MS * 2750, MS * 11_000));
+        // a low clock with a big increment is held back by the 40%
+        let time = TimeManager::from_clock(&clock(1000, 2000, None), Duration::ZERO, 20);
+        assert_eq!(limits(&time), (MS * 400, MS * 400));
+        // movestogo shares the clock out over the moves left
+        let time = TimeManager::from_clock(&clock(10_000, 0, Some(10)), Duration::ZERO, 20);
+        assert_eq!(limits(&time), (MS * 1000, MS * 4000));
+        // the last move of the session can use most of it
+        let time = TimeManager::from_clock(&clock(10_000, 0, Some(1)), Duration::ZERO, 20);
+        assert_eq!(limits(&time), (MS * 8000, MS * 8000));
+        // the overhead comes off first
+        let time = TimeManager::from_clock(&clock(60_030, 0, None), MS * 30, 20);
+        assert_eq!(limits(&time), (MS * 2000, MS * 8000));
+    }
+
+    #[test]
+    fn single_legal_move() {
+        let time = TimeManager::from_clock(&clock(60_000, 1000, None), Duration::ZERO, 1);
+        assert_eq!(limits(&time), (MS * 10, MS * 11_000));
+        assert!(time.soft_limit_reached(MS * 10));
+        // a nearly empty clock still wins over the 10ms
+        let time = TimeManager::from_clock(&clock(20, 0, None), Duration::ZERO, 1);
+        assert!(limits(&time).0 < MS * 10);
+    }
+
+    #[test]
+    fn hard_limit_stays_on_the_clock() {
+        for time_left in [0, 1, 10, 30, 31, 100, 1000, 60_000, 3_600_000] {
+            for increment in [0, 100, 5000] {
+                for moves_to_go in [None, Some(1), Some(2), Some(40), Some(100)] {
+                    for legal_moves in [1, 30] {
+                        let clock = clock(time_left, increment, moves_to_go);
+                        let mut time = TimeManager::from_clock(&clock, MS * 30, legal_moves);
+                        let (soft, hard) = limits(&time);
+                        assert!(hard <= clock.time_left.saturating_sub(MS * 30), "{:?} {} {:?}", clock.time_left, increment, moves_to_go);
+                        assert!(soft <= hard);
+                        // however unsure the search gets, it never runs past the hard limit
+                        for i in 0..10 {
+                            time.update(Some(ChessMove::new(Square::A2, ALL_SQUARES[16 + i], None)), -100 * i as i32);
+                        }
+                        assert!(time.soft_limit_reached(hard));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn search_trouble_stretches_the_soft_limit() {
+        let first = Some(ChessMove::new(Square::E2, Square::E4, None));
+        let second = Some(ChessMove::new(Square::D2, Square::D4, None));
+        let new = || TimeManager::from_clock(&clock(60_000, 0, None), Duration::ZERO, 20);
+
+        // the best move changed: 1.4 times as long
+        let mut time = new();
+        time.update(first, 0);
+        time.update(second, 0);
+        assert!(!time.soft_limit_reached(MS * 2799));
+        assert!(time.soft_limit_reached(MS * 2800));
+
+        // the score dropped by more than 30: 1.2 times as long
+        let mut time = new();
+        time.update(first, 0);
+        time.update(first, -31);
+        assert!(!time.soft_limit_reached(MS * 2399));
+        assert!(time.soft_limit_reached(MS * 2400));
+        // then both at once, 1.2 * 1.4 * 1.2
+        time.update(second, -100);
+        assert!(!time.soft_limit_reached(MS * 4031));
+        assert!(time.soft_limit_reached(MS * 4032));
+
+        // a small drop and the same move is nothing to worry about
+        let mut time = new();
+        time.update(first, 0);
+        time.update(first, -30);
+        assert!(time.soft_limit_reached(MS * 2000));
+
+        // a fixed move time is never stretched
+        let mut time = TimeManager::fixed(MS * 1000, Duration::ZERO);
+        time.update(first, 0);
+        time.update(second, -500);
+        assert!(time.soft_limit_reached(MS * 1000));
+    }
+
+    #[test]
+    fn fixed_move_time() {
+        let time = TimeManager::fixed(MS * 1000, MS * 30);
+        assert_eq!(limits(&time), (MS * 970, MS * 970));
+        let time = TimeManager::fixed(MS * 20, MS * 30);
+        assert_eq!(limits(&time), (Duration::ZERO, Duration::ZERO));
+    }
+}
@@ -0,0 +1,347 @@
+use chess::{ChessMove, Piece, ALL_SQUARES};
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+
+// A simple transposition table entry
+#[derive(Clone, Copy)]
+pub struct TTEntry {
+    pub depth: u32,
+    pub eval: i32,
+    pub flag: u8, // 0 = exact, 1 = lower bound, 2 = upper bound
+    pub best_move: Option<ChessMove>,
+}
+
+// one slot is two words: the entry packed into `data` and `key ^ data` in `check`.
+// a reader only trusts a slot when the two still agree, so a write from another thread
+// that lands halfway through a read just looks like a miss instead of garbage. no locks needed
+struct Slot {
+    check: AtomicU64,
+    data: AtomicU64,
+}
+
+const SLOTS_PER_BUCKET: usize = 4;
+
+// four slots fill exactly one 64 byte cache line, so a probe touches a single line
+#[repr(align(64))]
+struct Bucket {
+    slots: [Slot; SLOTS_PER_BUCKET],
+}
+
+// shared by every search thread, sized in megabytes and kept for the whole game
+pub struct TranspositionTable {
+    buckets: Vec<Bucket>,
+    mask: usize,
+    // bumped once per search, entries from older searches are the first to be replaced
+    generation: AtomicU8,
+}
+
+pub const DEFAULT_HASH_MB: usize = 16;
+pub const MAX_HASH_MB: usize = 65536;
+
+const GENERATIONS: u8 = 32;
+
+impl TranspositionTable {
+    // the bucket count is rounded down to a power of two so indexing is a mask
+    pub fn new(megabytes: usize) -> Self {
+        let bytes = megabytes.clamp(1, MAX_HASH_MB) * 1024 * 1024;
+        let wanted = bytes / size_of::<Bucket>();
+        let count = 1 << (usize::BITS - 1 - wanted.leading_zeros());
+        let buckets = (0..count)
+            .map(|_| Bucket {
+                slots: std::array::from_fn(|_| Slot {
+                    check: AtomicU64::new(0),
+                    data: AtomicU64::new(0),
+                }),
+            })
+            .collect();
+        TranspositionTable {
+            buckets,
+            mask: count - 1,
+            generation: AtomicU8::new(0),
+        }
+    }
+
+    pub fn clear(&self) {
+        for bucket in &self.buckets {
+            for slot in &bucket.slots {
+                slot.check.store(0, Ordering::Relaxed);
+                slot.data.store(0, Ordering::Relaxed);
+            }
+        }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+
+    // call before every search so this search's entries can be told from older ones
+    pub fn new_search(&self) {
+        let next = (self.generation.load(Ordering::Relaxed) + 1) % GENERATIONS;
+        self.generation.store(next, Ordering::Relaxed);
+    }
+
+    fn bucket(&self, hash: u64) -> &Bucket {
+        &self.buckets[hash as usize & self.mask]
+    }
+
+    // pull the bucket for `hash` into cache ahead of the probe (e.g. right after making a move)
+    pub fn prefetch(&self, hash: u64) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+            let bucket: *const Bucket = self.bucket(hash);
+            // SAFETY: prefetching is only a hint and the pointer is to a live bucket
+            unsafe { _mm_prefetch(bucket as *const i8, _MM_HINT_T0) };
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        let _ = hash;
+    }
+
+    pub fn get(&self, hash: u64) -> Option<TTEntry> {
+        for slot in &self.bucket(hash).slots {
+            let data = slot.data.load(Ordering::Relaxed);
+            let check = slot.check.load(Ordering::Relaxed);
+            if data != 0 && check ^ data == hash {
+                return Some(unpack(data));
+            }
+        }
+        None
+    }
+
+    // overwrites the same position if it's there, otherwise the slot with the least worth
+    // keeping: shallow entries and entries left over from earlier searches go first
+    pub fn insert(&self, hash: u64, entry: TTEntry) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        let bucket = self.bucket(hash);
+        let mut victim = &bucket.slots[0];
+        let mut victim_worth = i32::MAX;
+        for slot in &bucket.slots {
+            let data = slot.data.load(Ordering::Relaxed);
+            let check = slot.check.load(Ordering::Relaxed);
+            if data == 0 {
+                victim = slot;
+                break;
+            }
+            if check ^ data == hash {
+                // don't let a shallow non-exact result wipe out a deep one from this search
+                let old = unpack(data);
+                if entry.flag != 0 && entry.depth + 2 < old.depth && unpack_generation(data) == generation {
+                    return;
+                }
+                victim = slot;
+                break;
+            }
+            let age = (GENERATIONS + generation - unpack_generation(data)) % GENERATIONS;
+            let worth = unpack(data).depth as i32 - 8 * age as i32;
+            if worth < victim_worth {
+                victim = slot;
+                victim_worth = worth;
+            }
+        }
+        let data = pack(&entry, generation);
+        victim.data.store(data, Ordering::Relaxed);
+        victim.check.store(hash ^ data, Ordering::Relaxed);
+    }
+
+    // how full the table is in permille, counting only this search's entries (uci hashfull)
+    pub fn hashfull(&self) -> u32 {
+        let generation = self.generation.load(Ordering::Relaxed);
+        let sample = self.buckets.len().min(1000 / SLOTS_PER_BUCKET);
+        let mut used = 0;
+        for bucket in &self.buckets[..sample] {
+            for slot in &bucket.slots {
+                let data = slot.data.load(Ordering::Relaxed);
+                if data != 0 && unpack_generation(data) == generation {
+                    used += 1;
+                }
+            }
+        }
+        (used * 1000 / (sample * SLOTS_PER_BUCKET)) as u32
+    }
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        TranspositionTable::new(DEFAULT_HASH_MB)
+    }
+}
+
+// bits 0-15 move, 16-47 eval, 48-55 depth, 56-57 flag, 58 "occupied" so an entry never
+// packs to 0, 59-63 generation
+fn pack(entry: &TTEntry, generation: u8) -> u64 {
+    (pack_move(entry.best_move) as u64)
+        | ((entry.eval as u32 as u64) << 16)
+        | ((entry.depth.min(255) as u64) << 48)
+        | (((entry.flag & 3) as u64) << 56)
+        | (1 << 58)
+        | ((generation as u64) << 59)
+}
+
+fn unpack(data: u64) -> TTEntry {
+    TTEntry {
+        best_move: unpack_move(data as u16),
+        eval: (data >> 16) as u32 as i32,
+        depth: ((data >> 48) & 0xff) as u32,
+        flag: ((data >> 56) & 3) as u8,
+    }
+}
+
+fn unpack_generation(data: u64) -> u8 {
+    (data >> 59) as u8
+}
+
+// 6 bits source, 6 bits destination, 3 bits promotion piece (0 = none), bit 15 = has a move
+fn pack_move(m: Option<ChessMove>) -> u16 {
+    let Some(m) = m else {
+        return 0;
+    };
+    let promotion = match m.get_promotion() {
+        None => 0,
+        Some(Piece::Knight) => 1,
+        Some(Piece::Bishop) => 2,
+        Some(Piece::Rook) => 3,
+        Some(_) => 4,
+    };
+    (1 << 15) | (promotion << 12) | ((m.get_dest().to_index() as u16) << 6) | m.get_source().to_index() as u16
+}
+
+fn unpack_move(bits: u16) -> Option<ChessMove> {
+    if bits & (1 << 15) == 0 {
+        return None;
+    }
+    let promotion = match (bits >> 12) & 7 {
+        0 => None,
+        1 => Some(Piece::Knight),
+        2 => Some(Piece::Bishop),
+        3 => Some(Piece::Rook),
+        _ => Some(Piece::Queen),
+    };
+    let source = ALL_SQUARES[(bits & 63) as usize];
+    let dest = ALL_SQUARES[((bits >> 6) & 63) as usize];
+    Some(ChessMove::new(source, dest, promotion))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn entry(depth: u32, eval: i32, flag: u8, best_move: Option<&str>) -> TTEntry {
+        TTEntry {
+            depth,
+            eval,
+            flag,
+            best_move: best_move.map(|m| ChessMove::from_str(m).unwrap()),
+        }
+    }
+
+    fn assert_same(a: TTEntry, b: TTEntry) {
+        assert_eq!((a.depth, a.eval, a.flag, a.best_move), (b.depth, b.eval, b.flag, b.best_move));
+    }
+
+    // different positions that all land in the bucket `index` (a 1 MB table has far more buckets)
+    fn key(index: u64, n: u64) -> u64 {
+        (n + 1) << 40 | index
+    }
+
+    #[test]
+    fn packing_round_trips() {
+        let entries = [
+            entry(0, 0, 0, None),
+            entry(1, -1, 1, Some("e2e4")),
+            entry(255, 31_000, 2, Some("h7h8q")),
+            entry(17, -31_000, 0, Some("a2a1n")),
+            entry(3, i32::MAX, 1, Some("b7b8b")),
+            entry(4, i32::MIN, 2, Some("g2g1r")),
+            entry(9, 123, 0, Some("a1h8")),
+        ];
+        for generation in [0, 1, GENERATIONS - 1] {
+            for e in entries {
+                let data = pack(&e, generation);
+                assert_ne!(data, 0);
+                assert_same(unpack(data), e);
+                assert_eq!(unpack_generation(data), generation);
+            }
+        }
+        // too deep to fit is stored as the deepest that does
+        assert_eq!(unpack(pack(&entry(300, 0, 0, None), 0)).depth, 255);
+    }
+
+    #[test]
+    fn key_check() {
+        let tt = TranspositionTable::new(1);
+        let hash = key(5, 0);
+        assert!(tt.get(hash).is_none());
+        tt.insert(hash, entry(4, 50, 0, Some("g1f3")));
+        assert_same(tt.get(hash).unwrap(), entry(4, 50, 0, Some("g1f3")));
+        // same bucket, another position
+        assert!(tt.get(key(5, 1)).is_none());
+
+        // a write torn halfway (new data, old check) reads as a miss
+        let slot = &tt.bucket(hash).slots[0];
+        slot.data.store(pack(&entry(9, -7, 1, None), 0), Ordering::Relaxed);
+        assert!(tt.get(hash).is_none());
+    }
+
+    #[test]
+    fn replacement() {
+        let tt = TranspositionTable::new(1);
+        let depth_of = |n| tt.get(key(7, n)).map(|e| e.depth);
+
+        // the same position: a shallow bound doesn't replace a deep result, anything else does
+        tt.insert(key(7, 0), entry(10, 0, 0, None));
+        tt.insert(key(7, 0), entry(5, 0, 1, None));
+        assert_eq!(depth_of(0), Some(10));
+        tt.insert(key(7, 0), entry(8, 0, 2, None));
+        assert_eq!(depth_of(0), Some(8));
+        tt.insert(key(7, 0), entry(1, 0, 0, None));
+        assert_eq!(depth_of(0), Some(1));
+        tt.insert(key(7, 0), entry(12, 0, 0, None));
+        // from an older search it goes
+        tt.new_search();
+        tt.insert(key(7, 0), entry(2, 0, 1, None));
+        assert_eq!(depth_of(0), Some(2));
+
+        // a full bucket gives up its shallowest entry
+        let tt = TranspositionTable::new(1);
+        let depth_of = |n| tt.get(key(7, n)).map(|e| e.depth);
+        for (n, depth) in [9, 3, 7, 5].into_iter().enumerate() {
+            tt.insert(key(7, n as u64), entry(depth, 0, 0, None));
+        }
+        tt.insert(key(7, 4), entry(1, 0, 0, None));
+        assert_eq!(depth_of(1), None);
+        assert_eq!([0, 2, 3, 4].map(depth_of), [Some(9), Some(7), Some(5), Some(1)]);
+
+        // and an old entry goes before a shallower one from this search
+        let tt = TranspositionTable::new(1);
+        let depth_of = |n| tt.get(key(7, n)).map(|e| e.depth);
+        tt.insert(key(7, 0), entry(12, 0, 0, None));
+        tt.new_search();
+        for n in 1..4 {
+            tt.insert(key(7, n), entry(6, 0, 0, None));
+        }
+        tt.insert(key(7, 4), entry(1, 0, 0, None));
+        assert_eq!(depth_of(0), None);
+        assert_eq!([1, 2, 3, 4].map(depth_of), [Some(6), Some(6), Some(6), Some(1)]);
+    }
+
+    #[test]
+    fn hashfull() {
+        let tt = TranspositionTable::new(1);
+        assert_eq!(tt.hashfull(), 0);
+        // one slot in each of the sampled buckets, outside the sample doesn't count
+        for index in 0..(1000 / SLOTS_PER_BUCKET) as u64 {
+            tt.insert(key(index, 0), entry(1, 0, 0, None));
+        }
+        tt.insert(key(1000, 0), entry(1, 0, 0, None));
+        assert_eq!(tt.hashfull(), 250);
+        for n in 1..SLOTS_PER_BUCKET as u64 {
+            tt.insert(key(0, n), entry(1, 0, 0, None));
+        }
+        assert_eq!(tt.hashfull(), 253);
+        // only this search's entries count
+        tt.new_search();
+        assert_eq!(tt.hashfull(), 0);
+        tt.insert(key(3, 0), entry(1, 0, 0, None));
+        assert_eq!(tt.hashfull(), 1);
+        tt.clear();
+        assert_eq!(tt.hashfull(), 0);
+        assert!(tt.get(key(3, 0)).is_none());
+    }
+}
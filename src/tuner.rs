@@ -0,0 +1,257 @@
+use chess::{Board, Color};
+use crate::epd;
+use crate::eval::{self, EvalParams, DEFAULT_PARAMS};
+use std::fs;
+use std::str::FromStr;
+use std::thread;
+
+// texel tuning: find the eval weights that best predict game results. the eval is squashed
+// through a sigmoid into an expected score and compared with the result of the game each
+// position came from, then every weight is nudged up and down (local search) for as long as
+// that makes the mean squared error go down. works with any weight in EvalParams
+
+const USAGE: &str = "usage: tune <data file> [-passes N] [-threads N] [-limit N] [-out FILE]";
+
+// steps tried per weight, the big ones first to get going faster
+const STEPS: [i32; 4] = [8, 4, 2, 1];
+
+struct Settings {
+    path: String,
+    passes: usize,
+    threads: usize,
+    // use only the first this many positions, 0 for all
+    limit: usize,
+    out: String,
+}
+
+// a position and the result of its game from white's point of view (1.0 / 0.5 / 0.0)
+pub struct Sample {
+    pub board: Board,
+    pub result: f64,
+}
+
+// understands the selfplay format (`fen | score | move | ply | result`) and the usual labelled
+// epd/fen files, with the result in a `c9 "1-0"` operation or as the last thing on the line:
+// `[1.0]` / `[0.5]` / `[0.0]` or `"1-0"` / `"0-1"` / `"1/2-1/2"`. the rest of the line (ids,
+// comments) is never looked at, a "1-0" in there says nothing about this game
+pub fn parse_sample(line: &str) -> Option<Sample> {
+    if line.contains('|') {
+        let fields: Vec<&str> = line.split('|').map(str::trim).collect();
+        let board = Board::from_str(fields[0]).ok()?;
+        let result = fields.last()?.parse().ok()?;
+        return Some(Sample { board, result });
+    }
+    let fields: Vec<&str> = line.split_whitespace().take(4).collect();
+    let board = Board::from_str(&fields.join(" ")).ok()?;
+    let rest = (0..4).fold(line.trim_start(), |rest, _| rest.trim_start().split_once(char::is_whitespace).map_or("", |(_, rest)| rest));
+    let operations = epd::split_operations(rest);
+    let c9 = operations
+        .iter()
+        .find_map(|operation| operation.strip_prefix("c9").filter(|operand| operand.starts_with(char::is_whitespace)));
+    let result = match c9 {
+        Some(operand) => parse_result(epd::unquote(operand))?,
+        None => {
+            // on its own after the position, the move counters at most can come before it
+            let tokens: Vec<&str> = operations.last()?.split_whitespace().collect();
+            let (last, counters) = tokens.split_last()?;
+            if !counters.iter().all(|counter| counter.parse::<u32>().is_ok()) {
+                return None;
+            }
+            match last.strip_prefix('[').and_then(|last| last.strip_suffix(']')) {
+                Some(inner) => parse_result(inner)?,
+                // a bare number could be the fullmove counter, those only count in brackets
+                None => match last.strip_prefix('"').and_then(|last| last.strip_suffix('"')).unwrap_or(last) {
+                    text @ ("1-0" | "0-1" | "1/2-1/2") => parse_result(text)?,
+                    _ => return None,
+                },
+            }
+        }
+    };
+    Some(Sample { board, result })
+}
+
+fn parse_result(text: &str) -> Option<f64> {
+    match text {
+        "1-0" | "1.0" | "1" => Some(1.0),
+        "1/2-1/2" | "0.5" => Some(0.5),
+        "0-1" | "0.0" | "0" => Some(0.0),
+        _ => None,
+    }
+}
+
+// the static eval in white's point of view, the way the search sees it (from the side to move)
+fn white_eval(board: &Board, params: &EvalParams) -> i32 {
+    let side = board.side_to_move();
+    let score = eval::evaluate_with(*board, side, params);
+    if side == Color::White { score } else { -score }
+}
+
+fn sigmoid(score: f64, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * score / 400.0))
+}
+
+// mean squared error of the predicted scores, split over threads
+fn error(samples: &[Sample], params: &EvalParams, k: f64, threads: usize) -> f64 {
+    let chunk = samples.len().div_ceil(threads).max(1);
+    let total: f64 = thread::scope(|scope| {
+        let workers: Vec<_> = samples
+            .chunks(chunk)
+            .map(|part| {
+                scope.spawn(move || {
+                    part.iter()
+                        .map(|sample| (sample.result - sigmoid(white_eval(&sample.board, params) as f64, k)).powi(2))
+                        .sum::<f64>()
+                })
+            })
+            .collect();
+        workers.into_iter().map(|worker| worker.join().expect("tuner thread panicked")).sum()
+    });
+    total / samples.len() as f64
+}
+
+// the sigmoid scale that fits the current eval best, by narrowing in on the minimum
+fn fit_k(samples: &[Sample], params: &EvalParams, threads: usize) -> f64 {
+    let (mut low, mut high) = (0.0, 3.0);
+    let mut best = 1.0;
+    for _ in 0..4 {
+        let step = (high - low) / 10.0;
+        let mut best_error = f64::MAX;
+        for i in 0..=10 {
+            let k = low + step * i as f64;
+            let e = error(samples, params, k, threads);
+            if e < best_error {
+                best_error = e;
+                best = k;
+            }
+        }
+        low = (best - step).max(0.0);
+        high = best + step;
+    }
+    best
+}
+
+pub fn run(args: &[String]) {
+    let settings = match parse_args(args) {
+        Ok(settings) => settings,
+        Err(e) => {
+            println!("{}", e);
+            println!("{}", USAGE);
+            return;
+        }
+    };
+    let text = match fs::read_to_string(&settings.path) {
+        Ok(text) => text,
+        Err(e) => {
+            println!("can't read {}: {}", settings.path, e);
+            return;
+        }
+    };
+    let mut samples: Vec<Sample> = text.lines().filter_map(parse_sample).collect();
+    if settings.limit > 0 {
+        samples.truncate(settings.limit);
+    }
+    if samples.is_empty() {
+        println!("no labelled positions in {}", settings.path);
+        return;
+    }
+    println!("{} positions", samples.len());
+
+    let mut weights = DEFAULT_PARAMS.to_vec();
+    let k = fit_k(&samples, &DEFAULT_PARAMS, settings.threads);
+    let mut best_error = error(&samples, &DEFAULT_PARAMS, k, settings.threads);
+    println!("k {:.3} error {:.6}", k, best_error);
+
+    let mut step_index = 0;
+    for pass in 1..=settings.passes {
+        let step = STEPS[step_index];
+        let mut improved = 0;
+        for i in 0..weights.len() {
+            for delta in [step, -step] {
+                weights[i] += delta;
+                let e = error(&samples, &EvalParams::from_vec(&weights), k, settings.threads);
+                if e < best_error {
+                    best_error = e;
+                    improved += 1;
+                    break;
+                }
+                weights[i] -= delta;
+            }
+        }
+        println!("pass {} step {} error {:.6} ({} weights changed)", pass, step, best_error, improved);
+        // written every pass, so stopping the tuner early still leaves the best tables so far
+        if let Err(e) = fs::write(&settings.out, EvalParams::from_vec(&weights).to_rust()) {
+            println!("can't write {}: {}", settings.out, e);
+            return;
+        }
+        if improved == 0 {
+            if step_index + 1 == STEPS.len() {
+                break;
+            }
+            step_index += 1;
+        }
+    }
+    println!("tables written to {}, paste them over the ones in eval.rs", settings.out);
+}
+
+fn parse_args(args: &[String]) -> Result<Settings, String> {
+    let path = args.first().ok_or("no data file")?.clone();
+    let mut settings = Settings {
+        path,
+        passes: 100,
+        threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        limit: 0,
+        out: "tuned_eval.rs".to_string(),
+    };
+    for pair in args[1..].chunks(2) {
+        let flag = pair[0].as_str();
+        let value = pair.get(1).ok_or_else(|| format!("{} needs a value", flag))?;
+        let number = || value.parse::<usize>().map_err(|_| format!("not a number: {}", value));
+        match flag {
+            "-passes" => settings.passes = number()?,
+            "-threads" => settings.threads = number()?.max(1),
+            "-limit" => settings.limit = number()?,
+            "-out" => settings.out = value.clone(),
+            _ => return Err(format!("unknown flag {}", flag)),
+        }
+    }
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEN: &str = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq -";
+
+    fn result(line: &str) -> Option<f64> {
+        parse_sample(line).map(|sample| sample.result)
+    }
+
+    #[test]
+    fn results_come_from_their_field() {
+        assert_eq!(result(&format!("{} | 35 | e7e5 | 1 | 0.5", FEN)), Some(0.5));
+        assert_eq!(result(&format!("{} [1.0]", FEN)), Some(1.0));
+        assert_eq!(result(&format!("{} 0 1 [0]", FEN)), Some(0.0));
+        assert_eq!(result(&format!("{} [0.5];", FEN)), Some(0.5));
+        assert_eq!(result(&format!("{} \"0-1\";", FEN)), Some(0.0));
+        assert_eq!(result(&format!("{} 1/2-1/2", FEN)), Some(0.5));
+        assert_eq!(result(&format!("{} c9 \"1-0\";", FEN)), Some(1.0));
+        assert_eq!(result(&format!("{} id \"x\"; c9 \"1/2-1/2\"; c0 \"won 1-0 on time\";", FEN)), Some(0.5));
+        assert_eq!(parse_sample(&format!("{} c9 \"0-1\";", FEN)).unwrap().board, Board::from_str(FEN).unwrap());
+    }
+
+    #[test]
+    fn results_elsewhere_on_the_line_are_ignored() {
+        assert_eq!(result(&format!("{} id \"1-0 [1] 0-1\"; c9 \"0-1\";", FEN)), Some(0.0));
+        assert_eq!(result(&format!("{} id \"game 1-0\";", FEN)), None);
+        assert_eq!(result(&format!("{} c0 \"was [1] 0-1\";", FEN)), None);
+        assert_eq!(result(&format!("{} id \"0-1\";", FEN)), None);
+        assert_eq!(result(&format!("{} 0 1 \"1-0\"", FEN)), Some(1.0));
+        assert_eq!(result(&format!("{} c0 \"draw\"; [1.0]", FEN)), Some(1.0));
+        // the move counters aren't results
+        assert_eq!(result(&format!("{} 0 1", FEN)), None);
+        assert_eq!(result(&format!("{} [2.0]", FEN)), None);
+        assert_eq!(result(&format!("{} c9 \"white\";", FEN)), None);
+        assert_eq!(result("not a position [1.0]"), None);
+    }
+}
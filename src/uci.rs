@@ -0,0 +1,414 @@
+use chess::{Board, ChessMove, Color};
+use crate::bench;
+use crate::engine::{self, Engine, SearchHandle, SearchLimits, SearchResult};
+use crate::eval;
+use crate::nnue::Network;
+use crate::perft;
+use crate::timeman::Clock;
+use crate::tt;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+// everything that can follow `go`, times are in milliseconds
+#[derive(Default)]
+struct GoParams {
+    search_moves: Vec<ChessMove>,
+    depth: Option<u32>,
+    mate: Option<u32>,
+    nodes: Option<u64>,
+    movetime: Option<u64>,
+    wtime: Option<u64>,
+    btime: Option<u64>,
+    winc: Option<u64>,
+    binc: Option<u64>,
+    movestogo: Option<u64>,
+    infinite: bool,
+    ponder: bool,
+}
+
+const DEFAULT_MOVE_OVERHEAD: u64 = 30;
+
+// `first_command` is a line that was already read off stdin before we got here
+// (the interactive prompt reads "uci" while asking for a colour)
+pub fn run(first_command: Option<String>) {
+    let mut board = Board::default();
+    let mut engine = Engine::new();
+    let mut move_overhead = Duration::from_millis(DEFAULT_MOVE_OVERHEAD);
+    let mut multi_pv = 1;
+    // the network from EvalFile is only used while UseNNUE is on
+    let mut use_nnue = false;
+    let mut network: Option<Arc<Network>> = None;
+    // the search runs on its own thread so we can still answer isready and stop meanwhile
+    let mut search: Option<SearchHandle> = None;
+    let stdin = io::stdin();
+    let lines = first_command.into_iter().chain(stdin.lock().lines().map_while(Result::ok));
+    for line in lines {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let Some(&command) = tokens.first() else {
+            continue;
+        };
+        match command {
+            "uci" => {
+                println!("id name {} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+                println!("id author TheGamingSnail");
+                println!("option name Move Overhead type spin default {} min 0 max 5000", DEFAULT_MOVE_OVERHEAD);
+                println!("option name Hash type spin default {} min 1 max {}", tt::DEFAULT_HASH_MB, tt::MAX_HASH_MB);
+                println!("option name Clear Hash type button");
+                println!("option name Threads type spin default 1 min 1 max {}", engine::MAX_THREADS);
+                // only tells the gui that we can ponder, whether we do is up to its `go ponder`
+                println!("option name Ponder type check default false");
+                println!("option name MultiPV type spin default 1 min 1 max {}", engine::MAX_MULTI_PV);
+                println!("option name UseNNUE type check default false");
+                println!("option name EvalFile type string default <empty>");
+                println!("uciok");
+            }
+            "isready" => println!("readyok"),
+            "ucinewgame" => {
+                stop_search(&mut search);
+                engine.new_game();
+                board = Board::default();
+            }
+            "position" => {
+                stop_search(&mut search);
+                if let Some(new_board) = parse_position(&tokens[1..]) {
+                    board = new_board;
+                } else {
+                    println!("info string invalid position: {}", line);
+                }
+            }
+            // go perft <depth>: count the move tree of the current position instead of searching
+            "go" if tokens.get(1) == Some(&"perft") => {
+                stop_search(&mut search);
+                match tokens.get(2).and_then(|d| d.parse().ok()) {
+                    Some(depth) => perft::print_divide(&board, depth),
+                    None => println!("info string usage: go perft <depth>"),
+                }
+            }
+            "bench" => {
+                stop_search(&mut search);
+                bench::run(tokens.get(1).and_then(|d| d.parse().ok()).unwrap_or(bench::DEFAULT_BENCH_DEPTH));
+            }
+            // not uci, but handy from a console: the classical eval's terms for the position
+            "eval" => {
+                print!("{}", eval::trace(board));
+                if let Some(network) = network.as_ref().filter(|_| use_nnue) {
+                    println!("nnue evaluation {} (side to move's point of view)", network.evaluate_board(&board));
+                }
+            }
+            "perft" | "divide" => {
+                stop_search(&mut search);
+                match tokens.get(1).and_then(|d| d.parse().ok()) {
+                    Some(depth) if command == "divide" => perft::print_divide(&board, depth),
+                    Some(depth) => perft::print_perft(&board, depth),
+                    None => println!("info string usage: {} <depth>", command),
+                }
+            }
+            "go" => {
+                stop_search(&mut search);
+                let params = parse_go(&board, &tokens[1..]);
+                search = Some(go(&engine, board, &params, move_overhead, multi_pv));
+            }
+            "stop" => stop_search(&mut search),
+            // the ponder search carries on as the real one, now on our own clock
+            "ponderhit" => {
+                if let Some(handle) = &search {
+                    handle.ponderhit();
+                }
+            }
+            "setoption" => {
+                let (name, value) = parse_setoption(&tokens[1..]);
+                match (name.to_lowercase().as_str(), value.parse::<u64>()) {
+                    ("move overhead", Ok(millis)) => move_overhead = Duration::from_millis(millis),
+                    ("hash", Ok(megabytes)) => {
+                        stop_search(&mut search);
+                        engine.set_hash_size(megabytes as usize);
+                    }
+                    ("clear hash", _) => {
+                        stop_search(&mut search);
+                        engine.new_game();
+                    }
+                    ("threads", Ok(threads)) => {
+                        stop_search(&mut search);
+                        engine.set_threads(threads as usize);
+                    }
+                    ("ponder", _) => {}
+                    ("multipv", Ok(lines)) => multi_pv = (lines as usize).clamp(1, engine::MAX_MULTI_PV),
+                    ("usennue", _) => {
+                        stop_search(&mut search);
+                        use_nnue = value.eq_ignore_ascii_case("true");
+                        set_network(&mut engine, use_nnue, &network);
+                    }
+                    ("evalfile", _) => {
+                        stop_search(&mut search);
+                        if value.is_empty() || value == "<empty>" {
+                            network = None;
+                        } else {
+                            match Network::load(&value) {
+                                Ok(loaded) => {
+                                    println!("info string loaded {} ({} hidden neurons)", value, loaded.hidden_size());
+                                    network = Some(Arc::new(loaded));
+                                }
+                                // keep whatever was loaded before
+                                Err(e) => println!("info string {}", e),
+                            }
+                        }
+                        set_network(&mut engine, use_nnue, &network);
+                    }
+                    _ => println!("info string unknown option: {}", tokens[1..].join(" ")),
+                }
+            }
+            "quit" => break,
+            _ => println!("info string unknown command: {}", command),
+        }
+    }
+    stop_search(&mut search);
+}
+
+// stops the running search (if any) and waits until its bestmove is out
+fn stop_search(search: &mut Option<SearchHandle>) {
+    if let Some(handle) = search.take() {
+        handle.stop();
+        handle.join();
+    }
+}
+
+// position [startpos | fen <fen>] [moves <move1> ... <movei>]
+fn parse_position(tokens: &[&str]) -> Option<Board> {
+    let moves_index = tokens.iter().position(|t| *t == "moves").unwrap_or(tokens.len());
+    let mut board = match tokens.first() {
+        Some(&"startpos") => Board::default(),
+        Some(&"fen") => Board::from_str(&tokens[1..moves_index].join(" ")).ok()?,
+        _ => return None,
+    };
+    for move_text in tokens.iter().skip(moves_index + 1) {
+        let m = ChessMove::from_str(move_text).ok()?;
+        if !board.legal(m) {
+            return None;
+        }
+        board = board.make_move_new(m);
+    }
+    Some(board)
+}
+
+fn parse_go(board: &Board, tokens: &[&str]) -> GoParams {
+    let mut params = GoParams::default();
+    let mut i = 0;
+    while i < tokens.len() {
+        let value = tokens.get(i + 1).and_then(|v| v.parse::<u64>().ok());
+        match tokens[i] {
+            "searchmoves" => {
+                while let Some(m) = tokens.get(i + 1).and_then(|t| ChessMove::from_str(t).ok()) {
+                    if board.legal(m) {
+                        params.search_moves.push(m);
+                    }
+                    i += 1;
+                }
+            }
+            "depth" => {
+                params.depth = value.map(|v| v.min(u32::MAX as u64) as u32);
+                i += 1;
+            }
+            "mate" => {
+                params.mate = value.map(|v| v.min(u32::MAX as u64) as u32);
+                i += 1;
+            }
+            "nodes" => {
+                params.nodes = value;
+                i += 1;
+            }
+            "movetime" => {
+                params.movetime = value;
+                i += 1;
+            }
+            "wtime" | "btime" | "winc" | "binc" | "movestogo" => {
+                let field = match tokens[i] {
+                    "wtime" => &mut params.wtime,
+                    "btime" => &mut params.btime,
+                    "winc" => &mut params.winc,
+                    "binc" => &mut params.binc,
+                    _ => &mut params.movestogo,
+                };
+                *field = value;
+                i += 1;
+            }
+            "infinite" => params.infinite = true,
+            "ponder" => params.ponder = true,
+            _ => {}
+        }
+        i += 1;
+    }
+    params
+}
+
+// the network when it's switched on and loaded, otherwise back to the classical eval
+fn set_network(engine: &mut Engine, use_nnue: bool, network: &Option<Arc<Network>>) {
+    if use_nnue && network.is_none() {
+        println!("info string UseNNUE is on but no EvalFile is loaded, using the classical eval");
+    }
+    engine.set_network(network.clone().filter(|_| use_nnue));
+}
+
+// setoption name <id> [value <x>], both can contain spaces
+fn parse_setoption(tokens: &[&str]) -> (String, String) {
+    let value_index = tokens.iter().position(|t| *t == "value").unwrap_or(tokens.len());
+    let name = tokens.get(1..value_index).unwrap_or_default().join(" ");
+    let value = tokens.get(value_index + 1..).unwrap_or_default().join(" ");
+    (name, value)
+}
+
+fn go(engine: &Engine, board: Board, params: &GoParams, move_overhead: Duration, multi_pv: usize) -> SearchHandle {
+    let limits = search_limits(&board, params, move_overhead, multi_pv);
+    engine.spawn_search(board, limits, print_info, |result| {
+        match (result.best_move, result.ponder_move) {
+            (Some(best_move), Some(ponder_move)) => println!("bestmove {} ponder {}", best_move, ponder_move),
+            (Some(best_move), None) => println!("bestmove {}", best_move),
+            // mated or stalemated, uci wants a null move
+            (None, _) => println!("bestmove 0000"),
+        }
+    })
+}
+
+// the clock that matters is the side to move's
+fn search_limits(board: &Board, params: &GoParams, move_overhead: Duration, multi_pv: usize) -> SearchLimits {
+    let (time, inc) = match board.side_to_move() {
+        Color::White => (params.wtime, params.winc),
+        Color::Black => (params.btime, params.binc),
+    };
+    let clock = time.map(|time| Clock {
+        time_left: Duration::from_millis(time),
+        increment: Duration::from_millis(inc.unwrap_or(0)),
+        moves_to_go: params.movestogo.map(|moves| moves as u32),
+    });
+    let mut limits = SearchLimits {
+        // a mate in n needs 2n - 1 plies to see
+        depth: params.depth.or(params.mate.map(|n| n.saturating_mul(2).saturating_sub(1))),
+        nodes: params.nodes,
+        movetime: params.movetime.map(Duration::from_millis),
+        clock,
+        move_overhead,
+        search_moves: params.search_moves.clone(),
+        infinite: params.infinite,
+        ponder: params.ponder,
+        multi_pv,
+    };
+    // nothing limits the search, think for the usual amount of time
+    let unlimited = limits.depth.is_none() && limits.nodes.is_none() && limits.movetime.is_none() && limits.clock.is_none();
+    if unlimited && !limits.infinite {
+        limits.movetime = Some(engine::DEFAULT_MOVE_TIME);
+    }
+    limits
+}
+
+// one info line per multipv line, best first
+fn print_info(result: &SearchResult) {
+    for (rank, line) in result.lines.iter().enumerate() {
+        let score = match line.mate_in() {
+            Some(moves) => format!("mate {}", moves),
+            None => format!("cp {}", line.score),
+        };
+        println!(
+            "info depth {} multipv {} score {} nodes {} nps {} hashfull {} time {} pv {}",
+            result.depth,
+            rank + 1,
+            score,
+            result.nodes,
+            result.nps(),
+            result.hashfull,
+            result.elapsed.as_millis(),
+            line.pv_string()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fen(text: &str) -> Board {
+        Board::from_str(text).unwrap()
+    }
+
+    fn go_tokens(board: &Board, line: &str) -> GoParams {
+        parse_go(board, &line.split_whitespace().collect::<Vec<_>>())
+    }
+
+    fn limits(board: &Board, line: &str) -> SearchLimits {
+        search_limits(board, &go_tokens(board, line), Duration::from_millis(DEFAULT_MOVE_OVERHEAD), 1)
+    }
+
+    #[test]
+    fn position() {
+        let position = |line: &str| parse_position(&line.split_whitespace().collect::<Vec<_>>());
+        assert_eq!(position("startpos"), Some(Board::default()));
+        assert_eq!(
+            position("startpos moves e2e4 e7e5 g1f3"),
+            Some(fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"))
+        );
+        assert_eq!(position("fen 4k3/P7/8/8/8/8/8/4K3 w - - 0 40 moves a7a8q e8d7"), Some(fen("Q7/3k4/8/8/8/8/8/4K3 w - - 1 41")));
+        assert_eq!(position("fen 4k3/8/8/8/8/8/8/4K3 b - -"), Some(fen("4k3/8/8/8/8/8/8/4K3 b - - 0 1")));
+        // an illegal move anywhere throws the whole line out
+        assert_eq!(position("startpos moves e2e4 e2e4"), None);
+        assert_eq!(position("startpos moves e2e5"), None);
+        assert_eq!(position("startpos moves nonsense"), None);
+        assert_eq!(position("fen 4k3/8/8/8/8/8/8/4K3 x - - 0 1"), None);
+        assert_eq!(position(""), None);
+    }
+
+    #[test]
+    fn go_params() {
+        let board = Board::default();
+        let params = go_tokens(&board, "wtime 60000 btime 50000 winc 1000 binc 500 movestogo 20 ponder");
+        assert_eq!((params.wtime, params.btime, params.winc, params.binc), (Some(60000), Some(50000), Some(1000), Some(500)));
+        assert_eq!(params.movestogo, Some(20));
+        assert!(params.ponder && !params.infinite);
+
+        let params = go_tokens(&board, "depth 7 nodes 10000 movetime 250 infinite");
+        assert_eq!((params.depth, params.nodes, params.movetime), (Some(7), Some(10000), Some(250)));
+        assert!(params.infinite);
+
+        // searchmoves takes moves up to the next keyword, illegal ones are dropped
+        let params = go_tokens(&board, "searchmoves e2e4 e2e5 d2d4 depth 3");
+        let moves: Vec<String> = params.search_moves.iter().map(ChessMove::to_string).collect();
+        assert_eq!(moves, ["e2e4", "d2d4"]);
+        assert_eq!(params.depth, Some(3));
+
+        let params = go_tokens(&board, "depth x nodes");
+        assert_eq!((params.depth, params.nodes), (None, None));
+    }
+
+    #[test]
+    fn go_limits() {
+        let black = fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1");
+        let clocked = limits(&black, "wtime 60000 btime 50000 winc 1000 binc 500 movestogo 20");
+        let clock = clocked.clock.unwrap();
+        assert_eq!((clock.time_left, clock.increment, clock.moves_to_go), (Duration::from_secs(50), Duration::from_millis(500), Some(20)));
+        assert_eq!(clocked.move_overhead, Duration::from_millis(DEFAULT_MOVE_OVERHEAD));
+        assert_eq!(clocked.movetime, None);
+
+        let board = Board::default();
+        assert_eq!(limits(&board, "mate 3").depth, Some(5));
+        assert_eq!(limits(&board, "mate 3 depth 2").depth, Some(2));
+        assert_eq!(limits(&board, "mate 0").depth, Some(0));
+        // far too long a mate doesn't overflow
+        assert_eq!(limits(&board, "mate 4294967295").depth, Some(u32::MAX - 1));
+        assert_eq!(limits(&board, "mate 99999999999").depth, Some(u32::MAX - 1));
+
+        // nothing to go by is the usual think time, but infinite really is
+        assert_eq!(limits(&board, "").movetime, Some(engine::DEFAULT_MOVE_TIME));
+        assert_eq!(limits(&board, "infinite").movetime, None);
+        assert_eq!(limits(&board, "movetime 100").movetime, Some(Duration::from_millis(100)));
+        assert_eq!(limits(&board, "nodes 100").movetime, None);
+    }
+
+    #[test]
+    fn setoption() {
+        let option = |line: &str| parse_setoption(&line.split_whitespace().collect::<Vec<_>>());
+        assert_eq!(option("name Hash value 64"), ("Hash".to_string(), "64".to_string()));
+        assert_eq!(option("name Move Overhead value 100"), ("Move Overhead".to_string(), "100".to_string()));
+        assert_eq!(option("name Clear Hash"), ("Clear Hash".to_string(), String::new()));
+        assert_eq!(option("name EvalFile value nets/my net.bin"), ("EvalFile".to_string(), "nets/my net.bin".to_string()));
+        assert_eq!(option(""), (String::new(), String::new()));
+    }
+}
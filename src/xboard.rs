@@ -0,0 +1,425 @@
+use chess::{Board, BoardStatus, ChessMove, Color};
+use crate::engine::{self, Engine, SearchHandle, SearchLimits, SearchResult};
+use crate::timeman::Clock;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+// winboard / xboard (cecp protocol version 2) front-end
+
+// xboard has no option for it, so keep a fixed safety margin for the pipe round trip
+const MOVE_OVERHEAD: Duration = Duration::from_millis(30);
+
+struct XBoard {
+    // every position of the game so far, the last one is the current position.
+    // undo and remove just pop off the end
+    history: Vec<Board>,
+    // the fullmove number of the first position in history, from the fen after a setboard
+    first_move_number: u32,
+    // None while in force mode, otherwise the side the engine plays
+    engine_color: Option<Color>,
+    // set by sd and st, None means no limit of that kind
+    depth: Option<u32>,
+    movetime: Option<Duration>,
+    // from level: moves per session (0 for the whole game) and increment
+    moves_per_session: u32,
+    increment: Duration,
+    // our clock as last reported by `time`
+    time_left: Option<Duration>,
+    post: bool,
+    // the search we are waiting on, tagged with an id so a result that arrives after the
+    // search was called off can be told apart from the current one
+    thinking: Option<(u32, SearchHandle)>,
+    engine: Engine,
+    search_id: u32,
+    events: Sender<Event>,
+}
+
+// the main loop waits on both the gui and the search thread
+enum Event {
+    Input(String),
+    SearchDone(u32, SearchResult),
+}
+
+pub fn run(first_command: Option<String>) {
+    let (events, inbox) = mpsc::channel();
+    let input = events.clone();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let lines = first_command.into_iter().chain(stdin.lock().lines().map_while(Result::ok));
+        for line in lines {
+            if input.send(Event::Input(line)).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut xboard = XBoard::new(events);
+    for event in inbox {
+        let line = match event {
+            Event::Input(line) => line,
+            Event::SearchDone(id, result) => {
+                xboard.search_done(id, &result);
+                continue;
+            }
+        };
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let Some(&command) = tokens.first() else {
+            continue;
+        };
+        let args = &tokens[1..];
+        match command {
+            // sent to switch into this mode, nothing to answer
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" | "draw" => {}
+            "protover" => {
+                println!(
+                    "feature myname=\"{} {}\" ping=1 setboard=1 usermove=1 sigint=0 sigterm=0 colors=0 analyze=0 smp=1 memory=1 done=1",
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_VERSION")
+                );
+            }
+            "ping" => println!("pong {}", args.join(" ")),
+            "new" => {
+                xboard.cancel_search();
+                xboard.engine.new_game();
+                xboard.history = vec![Board::default()];
+                xboard.first_move_number = 1;
+                xboard.engine_color = Some(Color::Black);
+                xboard.depth = None;
+                xboard.movetime = None;
+                xboard.time_left = None;
+            }
+            "force" | "result" => {
+                xboard.cancel_search();
+                xboard.engine_color = None;
+            }
+            "go" => {
+                xboard.cancel_search();
+                xboard.engine_color = Some(xboard.board().side_to_move());
+                xboard.think();
+            }
+            "usermove" => match args.first().and_then(|m| ChessMove::from_str(m).ok()) {
+                Some(m) if xboard.board().legal(m) => {
+                    xboard.play(m);
+                    if xboard.engine_color == Some(xboard.board().side_to_move()) {
+                        xboard.think();
+                    }
+                }
+                _ => println!("Illegal move: {}", args.join(" ")),
+            },
+            "setboard" => {
+                xboard.cancel_search();
+                if !xboard.set_board(args) {
+                    println!("tellusererror Illegal position");
+                }
+            }
+            "undo" => {
+                xboard.cancel_search();
+                xboard.take_back(1);
+            }
+            // our move and the opponent's before it
+            "remove" => {
+                xboard.cancel_search();
+                xboard.take_back(2);
+            }
+            "sd" => {
+                if let Some(depth) = args.first().and_then(|d| d.parse().ok()) {
+                    xboard.depth = Some(depth);
+                }
+            }
+            "st" => {
+                // negative, nan and infinite times are ignored
+                if let Some(movetime) = args.first().and_then(|s| s.parse::<f64>().ok()).and_then(|s| Duration::try_from_secs_f64(s).ok()) {
+                    xboard.movetime = Some(movetime);
+                }
+            }
+            // level MPS BASE INC, base is minutes or minutes:seconds, increment in seconds
+            "level" => match parse_level(args) {
+                Some((moves_per_session, base, increment)) => {
+                    xboard.moves_per_session = moves_per_session;
+                    xboard.increment = increment;
+                    xboard.time_left = Some(base);
+                    xboard.movetime = None;
+                }
+                None => println!("Error (bad level): {}", args.join(" ")),
+            },
+            // our remaining time in centiseconds
+            "time" => {
+                if let Some(centis) = args.first().and_then(|t| t.parse::<u64>().ok()) {
+                    xboard.time_left = Some(Duration::from_millis(centis * 10));
+                }
+            }
+            // the opponent's clock doesn't change how we spend ours
+            "otim" => {}
+            // hash size in megabytes
+            "memory" => {
+                if let Some(megabytes) = args.first().and_then(|m| m.parse().ok()) {
+                    xboard.cancel_search();
+                    xboard.engine.set_hash_size(megabytes);
+                }
+            }
+            // number of search threads to use
+            "cores" => {
+                if let Some(cores) = args.first().and_then(|c| c.parse().ok()) {
+                    xboard.cancel_search();
+                    xboard.engine.set_threads(cores);
+                }
+            }
+            "post" => xboard.post = true,
+            "nopost" => xboard.post = false,
+            // move now: the search stops and plays what it has through search_done
+            "?" => {
+                if let Some((_, handle)) = &xboard.thinking {
+                    handle.stop();
+                }
+            }
+            "quit" => {
+                xboard.cancel_search();
+                break;
+            }
+            _ => println!("Error (unknown command): {}", command),
+        }
+    }
+}
+
+impl XBoard {
+    fn new(events: Sender<Event>) -> Self {
+        XBoard {
+            history: vec![Board::default()],
+            first_move_number: 1,
+            engine_color: Some(Color::Black),
+            depth: None,
+            movetime: None,
+            moves_per_session: 0,
+            increment: Duration::ZERO,
+            time_left: None,
+            post: false,
+            thinking: None,
+            engine: Engine::new(),
+            search_id: 0,
+            events,
+        }
+    }
+
+    fn board(&self) -> Board {
+        *self.history.last().expect("history always holds the current position")
+    }
+
+    fn play(&mut self, m: ChessMove) {
+        let new_board = self.board().make_move_new(m);
+        self.history.push(new_board);
+    }
+
+    // false (and nothing changes) when the fen is no good
+    fn set_board(&mut self, fen: &[&str]) -> bool {
+        let Ok(board) = Board::from_str(&fen.join(" ")) else {
+            return false;
+        };
+        self.history = vec![board];
+        // the chess crate drops the move counters, so read it off the fen
+        self.first_move_number = fen.get(5).and_then(|n| n.parse().ok()).unwrap_or(1).max(1);
+        true
+    }
+
+    // goes back `plies` half-moves, or as far as the first position if there aren't that many
+    fn take_back(&mut self, plies: usize) {
+        self.history.truncate(self.history.len().saturating_sub(plies).max(1));
+    }
+
+    fn think(&mut self) {
+        let board = self.board();
+        if board.status() != BoardStatus::Ongoing {
+            self.report_result();
+            return;
+        }
+        let clock = self.time_left.map(|time_left| Clock {
+            time_left,
+            increment: self.increment,
+            moves_to_go: self.moves_to_go(),
+        });
+        let mut limits = SearchLimits {
+            depth: self.depth,
+            movetime: self.movetime,
+            clock,
+            move_overhead: MOVE_OVERHEAD,
+            ..SearchLimits::default()
+        };
+        if limits.depth.is_none() && limits.movetime.is_none() && limits.clock.is_none() {
+            limits.movetime = Some(engine::DEFAULT_MOVE_TIME);
+        }
+        let post = self.post;
+        self.search_id += 1;
+        let id = self.search_id;
+        let events = self.events.clone();
+        let handle = self.engine.spawn_search(
+            board,
+            limits,
+            move |result| {
+                if post {
+                    print_thinking(result);
+                }
+            },
+            move |result| {
+                let _ = events.send(Event::SearchDone(id, result.clone()));
+            },
+        );
+        self.thinking = Some((id, handle));
+    }
+
+    fn search_done(&mut self, id: u32, result: &SearchResult) {
+        // a search we already called off, its move is no good anymore
+        if !matches!(&self.thinking, Some((current, _)) if *current == id) {
+            return;
+        }
+        if let Some((_, handle)) = self.thinking.take() {
+            handle.join();
+        }
+        let Some(best_move) = result.best_move else {
+            return;
+        };
+        println!("move {}", best_move);
+        self.play(best_move);
+        self.report_result();
+    }
+
+    // stops the current search without playing its move
+    fn cancel_search(&mut self) {
+        if let Some((_, handle)) = self.thinking.take() {
+            handle.stop();
+            handle.join();
+        }
+    }
+
+    // moves left until the clock gets topped up again, only for classical controls
+    fn moves_to_go(&self) -> Option<u32> {
+        if self.moves_per_session == 0 {
+            return None;
+        }
+        Some(self.moves_per_session - (self.move_number() - 1) % self.moves_per_session)
+    }
+
+    // the fullmove number of the current position, it goes up after every black move
+    fn move_number(&self) -> u32 {
+        let played = &self.history[..self.history.len() - 1];
+        self.first_move_number + played.iter().filter(|board| board.side_to_move() == Color::Black).count() as u32
+    }
+
+    fn report_result(&self) {
+        let board = self.board();
+        match board.status() {
+            BoardStatus::Checkmate if board.side_to_move() == Color::White => println!("0-1 {{Black mates}}"),
+            BoardStatus::Checkmate => println!("1-0 {{White mates}}"),
+            BoardStatus::Stalemate => println!("1/2-1/2 {{Stalemate}}"),
+            BoardStatus::Ongoing => {}
+        }
+    }
+}
+
+fn parse_level(args: &[&str]) -> Option<(u32, Duration, Duration)> {
+    let moves_per_session = args.first()?.parse().ok()?;
+    let base = match args.get(1)?.split_once(':') {
+        Some((minutes, seconds)) => minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?,
+        None => args.get(1)?.parse::<u64>().ok()? * 60,
+    };
+    let increment = Duration::try_from_secs_f64(args.get(2)?.parse::<f64>().ok()?).ok()?;
+    Some((moves_per_session, Duration::from_secs(base), increment))
+}
+
+// ply score time(centiseconds) nodes pv
+fn print_thinking(result: &SearchResult) {
+    if result.pv.is_empty() {
+        return;
+    }
+    // xboard wants mates as 100000 + moves to mate
+    let score = match result.mate_in() {
+        Some(moves) if moves > 0 => 100000 + moves,
+        Some(moves) => -100000 + moves,
+        None => result.score,
+    };
+    println!(
+        "{} {} {} {} {}",
+        result.depth,
+        score,
+        result.elapsed.as_millis() / 10,
+        result.nodes,
+        result.pv_string()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xboard() -> XBoard {
+        XBoard::new(mpsc::channel().0)
+    }
+
+    fn play(xboard: &mut XBoard, moves: &[&str]) {
+        for m in moves {
+            xboard.play(ChessMove::from_str(m).unwrap());
+        }
+    }
+
+    #[test]
+    fn level() {
+        let secs = Duration::from_secs;
+        assert_eq!(parse_level(&["40", "5", "0"]), Some((40, secs(300), Duration::ZERO)));
+        assert_eq!(parse_level(&["0", "2:30", "2"]), Some((0, secs(150), secs(2))));
+        assert_eq!(parse_level(&["0", "0:05", "0.5"]), Some((0, secs(5), Duration::from_millis(500))));
+        for bad in [&["0", "5"][..], &["x", "5", "0"], &["0", "5:x", "0"], &["0", "-5", "0"]] {
+            assert_eq!(parse_level(bad), None, "{:?}", bad);
+        }
+        for increment in ["-1", "nan", "inf", "x"] {
+            assert_eq!(parse_level(&["0", "5", increment]), None, "{}", increment);
+        }
+    }
+
+    #[test]
+    fn moves_to_go_counts_from_the_fen_move_number() {
+        let mut xboard = xboard();
+        assert_eq!(xboard.moves_to_go(), None);
+        xboard.moves_per_session = 40;
+        assert_eq!(xboard.moves_to_go(), Some(40));
+        play(&mut xboard, &["e2e4", "e7e5"]);
+        assert_eq!(xboard.moves_to_go(), Some(39));
+        // black's move 2 is still in move 2
+        play(&mut xboard, &["g1f3"]);
+        assert_eq!(xboard.moves_to_go(), Some(39));
+
+        assert!(xboard.set_board(&["4k3/8/8/8/8/8/8/4K3", "b", "-", "-", "0", "38"]));
+        assert_eq!(xboard.moves_to_go(), Some(3));
+        play(&mut xboard, &["e8d8", "e1d1"]);
+        assert_eq!(xboard.moves_to_go(), Some(2));
+        play(&mut xboard, &["d8c8", "d1c1", "c8b8", "c1b1"]);
+        // move 41 starts the next session
+        assert_eq!(xboard.moves_to_go(), Some(40));
+
+        // no move counters means move 1
+        assert!(xboard.set_board(&["4k3/8/8/8/8/8/8/4K3", "w", "-", "-"]));
+        assert_eq!(xboard.moves_to_go(), Some(40));
+        assert!(!xboard.set_board(&["not", "a", "position"]));
+        assert_eq!(xboard.board(), Board::from_str("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap());
+    }
+
+    #[test]
+    fn undo_and_remove() {
+        let mut xboard = xboard();
+        play(&mut xboard, &["e2e4", "e7e5", "g1f3"]);
+        xboard.take_back(1);
+        assert_eq!(xboard.history.len(), 3);
+        xboard.take_back(2);
+        assert_eq!(xboard.board(), Board::default());
+        xboard.take_back(1);
+        assert_eq!(xboard.board(), Board::default());
+
+        // remove after just one move still goes back to the start
+        assert!(xboard.set_board(&["4k3/8/8/8/8/8/8/4K3", "w", "-", "-", "0", "10"]));
+        let start = xboard.board();
+        play(&mut xboard, &["e1d1"]);
+        xboard.take_back(2);
+        assert_eq!(xboard.board(), start);
+        assert_eq!(xboard.history.len(), 1);
+    }
+}
@@ -0,0 +1,152 @@
+use chess::{Board, ChessMove, Piece, Square};
+use chessengine_plsbuild::perft::{divide, perft};
+use std::str::FromStr;
+
+// the standard perft positions, counts from the chess programming wiki.
+// the cheap depths run with every `cargo test`, the deep ones are ignored because they take
+// minutes in a debug build: `cargo test --release -- --ignored`.
+// no chess960 positions: the chess crate only knows standard castling, so it can neither read
+// shredder-fen castling rights nor generate 960 castles, and the published counts need both
+
+const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+const POSITION_3: &str = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+const POSITION_4: &str = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+// position 4 with colours flipped, should give the same numbers
+const POSITION_4_MIRRORED: &str = "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1";
+const POSITION_5: &str = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+const POSITION_6: &str = "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
+
+fn check(fen: &str, counts: &[u64]) {
+    let board = Board::from_str(fen).expect("valid fen");
+    for (depth, expected) in counts.iter().enumerate() {
+        let depth = depth as u32 + 1;
+        assert_eq!(perft(&board, depth), *expected, "perft({}) of {}", depth, fen);
+    }
+}
+
+#[test]
+fn startpos() {
+    check(STARTPOS, &[20, 400, 8902, 197281, 4865609]);
+}
+
+#[test]
+#[ignore = "slow, run with --release -- --ignored"]
+fn startpos_deep() {
+    check(STARTPOS, &[20, 400, 8902, 197281, 4865609, 119060324]);
+}
+
+#[test]
+fn kiwipete() {
+    check(KIWIPETE, &[48, 2039, 97862, 4085603]);
+}
+
+#[test]
+#[ignore = "slow, run with --release -- --ignored"]
+fn kiwipete_deep() {
+    check(KIWIPETE, &[48, 2039, 97862, 4085603, 193690690]);
+}
+
+#[test]
+fn position_3() {
+    check(POSITION_3, &[14, 191, 2812, 43238, 674624]);
+}
+
+#[test]
+#[ignore = "slow, run with --release -- --ignored"]
+fn position_3_deep() {
+    check(POSITION_3, &[14, 191, 2812, 43238, 674624, 11030083, 178633661]);
+}
+
+#[test]
+fn position_4() {
+    check(POSITION_4, &[6, 264, 9467, 422333]);
+    check(POSITION_4_MIRRORED, &[6, 264, 9467, 422333]);
+}
+
+#[test]
+#[ignore = "slow, run with --release -- --ignored"]
+fn position_4_deep() {
+    check(POSITION_4, &[6, 264, 9467, 422333, 15833292]);
+}
+
+#[test]
+fn position_5() {
+    check(POSITION_5, &[44, 1486, 62379, 2103487]);
+}
+
+#[test]
+#[ignore = "slow, run with --release -- --ignored"]
+fn position_5_deep() {
+    check(POSITION_5, &[44, 1486, 62379, 2103487, 89941194]);
+}
+
+#[test]
+fn position_6() {
+    check(POSITION_6, &[46, 2079, 89890, 3894594]);
+}
+
+#[test]
+#[ignore = "slow, run with --release -- --ignored"]
+fn position_6_deep() {
+    check(POSITION_6, &[46, 2079, 89890, 3894594, 164075551]);
+}
+
+#[test]
+fn divide_adds_up_to_perft() {
+    let board = Board::from_str(KIWIPETE).unwrap();
+    let split = divide(&board, 3);
+    assert_eq!(split.len(), 48);
+    assert_eq!(split.iter().map(|(_, nodes)| nodes).sum::<u64>(), perft(&board, 3));
+}
+
+// null move pruning searches the position after passing, which must be the same position
+// with the other side to move and the en passant square gone
+#[test]
+fn null_move_flips_side_to_move() {
+    let cases = [
+        (STARTPOS, "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1"),
+        (POSITION_6, "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 b - - 0 10"),
+        (
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 3",
+        ),
+    ];
+    for (fen, passed) in cases {
+        let board = Board::from_str(fen).unwrap().null_move().expect("not in check");
+        let expected = Board::from_str(passed).unwrap();
+        assert_eq!(board.get_hash(), expected.get_hash(), "null move of {}", fen);
+        assert_eq!(perft(&board, 3), perft(&expected, 3), "null move of {}", fen);
+    }
+    // can't pass while in check
+    let in_check = Board::from_str("4k3/8/8/8/8/8/8/4K2r w - - 0 1").unwrap();
+    assert!(in_check.null_move().is_none());
+}
+
+// what the interactive game builds from "e7e8q" and friends: every promotion piece is a
+// separate legal move, and a missing promotion piece is not legal
+#[test]
+fn promotions() {
+    let board = Board::from_str("8/4P3/8/8/8/8/k7/4K3 w - - 0 1").unwrap();
+    for piece in [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight] {
+        assert!(board.legal(ChessMove::new(Square::E7, Square::E8, Some(piece))));
+    }
+    assert!(!board.legal(ChessMove::new(Square::E7, Square::E8, None)));
+    assert_eq!(ChessMove::from_str("e7e8n").unwrap(), ChessMove::new(Square::E7, Square::E8, Some(Piece::Knight)));
+    assert_eq!(perft(&board, 1), 4 + 5);
+}
+
+#[test]
+fn castling_and_en_passant() {
+    let board = Board::from_str("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+    assert!(board.legal(ChessMove::new(Square::E1, Square::G1, None)));
+    assert!(board.legal(ChessMove::new(Square::E1, Square::C1, None)));
+    // a king passing through an attacked square can't castle
+    let board = Board::from_str("r3k2r/8/8/8/8/8/5r2/R3K2R w KQkq - 0 1").unwrap();
+    assert!(!board.legal(ChessMove::new(Square::E1, Square::G1, None)));
+
+    let board = Board::from_str("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3").unwrap();
+    let capture = ChessMove::new(Square::E5, Square::F6, None);
+    assert!(board.legal(capture));
+    assert!(board.make_move_new(capture).piece_on(Square::F5).is_none());
+}
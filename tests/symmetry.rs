@@ -0,0 +1,227 @@
+use chess::{Board, BoardBuilder, BoardStatus, ChessMove, Color, MoveGen, ALL_SQUARES};
+use chessengine_plsbuild::engine::{Engine, SearchLimits, SearchResult, SearchSignals, MATE_SCORE};
+use chessengine_plsbuild::eval;
+use chessengine_plsbuild::nnue::Network;
+use chessengine_plsbuild::pawns::PawnTable;
+use chessengine_plsbuild::rng::Rng;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// the evaluation and the search are both for the side to move (negamax), so a position and the
+// same position with the colours swapped (board upside down, white and black pieces traded, the
+// other side to move) have to come out exactly the same
+
+const POSITIONS: [&str; 12] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 10",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 11",
+    "4rrk1/pp1n3p/3q2pQ/2p1pb2/2PP4/2P3N1/P2B2PP/4RRK1 b - - 7 19",
+    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "rnbqkb1r/pp1p1ppp/4pn2/2pP4/2P5/8/PP2PPPP/RNBQKBNR w KQkq c6 0 4",
+    "2r3k1/pp3ppp/4p3/3pP3/3P4/P4N2/1P3PPP/2R3K1 b - - 0 24",
+    "8/8/4k3/3p4/3P4/4K3/8/8 w - - 0 50",
+    "6k1/8/6K1/6P1/8/8/8/8 w - - 0 60",
+    "8/5k2/8/8/3K4/8/1Q6/8 b - - 0 70",
+    "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+];
+
+// the colour-swapped board: upside down, white and black pieces traded, the other side to move
+// and castling rights traded. the en passant file stays
+fn flip(board: &Board) -> Board {
+    let mut builder = BoardBuilder::new();
+    for square in *board.combined() {
+        let piece = board.piece_on(square).unwrap();
+        let color = board.color_on(square).unwrap();
+        builder.piece(ALL_SQUARES[square.to_index() ^ 56], piece, !color);
+    }
+    builder
+        .side_to_move(!board.side_to_move())
+        .castle_rights(Color::White, board.castle_rights(Color::Black))
+        .castle_rights(Color::Black, board.castle_rights(Color::White))
+        .en_passant(board.en_passant().map(|square| square.get_file()));
+    Board::try_from(&builder).unwrap_or_else(|e| panic!("{}: {}", board, e))
+}
+
+fn board(fen: &str) -> Board {
+    Board::from_str(fen).unwrap_or_else(|e| panic!("{}: {}", fen, e))
+}
+
+// every position up to two plies from each of POSITIONS
+fn positions() -> Vec<Board> {
+    let mut boards = Vec::new();
+    for fen in POSITIONS {
+        let root = board(fen);
+        boards.push(root);
+        for first in MoveGen::new_legal(&root) {
+            let child = root.make_move_new(first);
+            boards.push(child);
+            for second in MoveGen::new_legal(&child) {
+                boards.push(child.make_move_new(second));
+            }
+        }
+    }
+    boards
+}
+
+fn search(engine: &Engine, board: Board, depth: u32) -> SearchResult {
+    engine.new_game();
+    let limits = SearchLimits {
+        depth: Some(depth),
+        ..SearchLimits::default()
+    };
+    engine.search(board, &limits, &SearchSignals::default(), |_| {})
+}
+
+#[test]
+fn flip_round_trips() {
+    for position in positions() {
+        let flipped = flip(&position);
+        assert_eq!(flip(&flipped), position);
+        assert_eq!(MoveGen::new_legal(&flipped).len(), MoveGen::new_legal(&position).len(), "{}", position);
+        assert_eq!(flipped.en_passant().is_some(), position.en_passant().is_some(), "{}", position);
+    }
+    assert_eq!(flip(&board(POSITIONS[6])).en_passant().map(|square| square.to_string()), Some("c4".to_string()));
+}
+
+#[test]
+fn eval_is_symmetric() {
+    let mut pawn_table = PawnTable::new();
+    for position in positions() {
+        let flipped = flip(&position);
+        let side = position.side_to_move();
+        let score = eval::evaluate(position, side);
+        assert_eq!(score, -eval::evaluate(position, !side), "{}", position);
+        assert_eq!(score, eval::evaluate(flipped, !side), "{}", position);
+        assert_eq!(score, eval::evaluate_cached(position, &mut pawn_table), "{}", position);
+        assert_eq!(score, eval::evaluate_cached(flipped, &mut pawn_table), "{}", position);
+    }
+}
+
+#[test]
+fn eval_trace_is_symmetric() {
+    for position in positions() {
+        let trace = eval::trace(position);
+        let flipped = eval::trace(flip(&position));
+        assert_eq!(trace.score, eval::evaluate(position, Color::White), "{}", position);
+        assert_eq!(trace.score, -flipped.score, "{}", position);
+        assert_eq!(trace.terms.len(), flipped.terms.len());
+        for (term, flipped_term) in trace.terms.iter().zip(&flipped.terms) {
+            assert_eq!(term.sides, [flipped_term.sides[1], flipped_term.sides[0]], "{} in {}", term.name, position);
+        }
+    }
+}
+
+// a small net with random weights, the features see the board from each side so it has to
+// come out symmetric as well
+fn random_network() -> Network {
+    let hidden = 16;
+    let mut rng = Rng::new(25);
+    let mut weights = |count: usize, range: usize| -> Vec<i16> { (0..count).map(|_| rng.below(2 * range) as i16 - range as i16).collect() };
+    let feature_weights = weights(768 * hidden, 64);
+    let feature_biases = weights(hidden, 64);
+    let output_weights = weights(2 * hidden, 64);
+    Network::new(hidden, feature_weights, feature_biases, output_weights, 1000).unwrap()
+}
+
+#[test]
+fn nnue_is_symmetric() {
+    let network = random_network();
+    for position in positions() {
+        let score = network.evaluate_board(&position);
+        assert_eq!(score, network.evaluate_board(&flip(&position)), "{}", position);
+    }
+}
+
+// the shallow searches are exact whatever order the moves are tried in, so the scores match.
+// deeper ones reduce late moves and the move generator doesn't list moves in mirrored order
+#[test]
+fn search_is_symmetric() {
+    let mut engine = Engine::new();
+    engine.set_hash_size(4);
+    for fen in POSITIONS {
+        let position = board(fen);
+        for depth in 1..=2 {
+            let score = search(&engine, position, depth).score;
+            assert_eq!(score, search(&engine, flip(&position), depth).score, "depth {} {}", depth, fen);
+        }
+    }
+    // one ply is enough with the random net, its wild scores make for huge quiescence searches
+    engine.set_network(Some(Arc::new(random_network())));
+    for fen in POSITIONS {
+        let position = board(fen);
+        let score = search(&engine, position, 1).score;
+        assert_eq!(score, search(&engine, flip(&position), 1).score, "nnue {}", fen);
+    }
+}
+
+// quiescence search without alpha-beta: stand pat, then every capture (every move in check).
+// out of check it skips the same clearly losing captures the engine's does
+fn reference_quiescence(board: Board, ply: i32) -> i32 {
+    match board.status() {
+        BoardStatus::Checkmate => return -(MATE_SCORE - ply),
+        BoardStatus::Stalemate => return 0,
+        BoardStatus::Ongoing => {}
+    }
+    let in_check = board.checkers().popcnt() > 0;
+    let mut moves = MoveGen::new_legal(&board);
+    if !in_check {
+        moves.set_iterator_mask(*board.color_combined(!board.side_to_move()));
+    }
+    let losing = |m: &ChessMove| match (board.piece_on(m.get_source()), board.piece_on(m.get_dest())) {
+        (Some(moving), Some(captured)) => eval::get_piece_value(moving) > eval::get_piece_value(captured) + 100,
+        _ => false,
+    };
+    let stand_pat = eval::evaluate(board, board.side_to_move());
+    moves
+        .filter(|m| in_check || !losing(m))
+        .fold(stand_pat, |best, m| best.max(-reference_quiescence(board.make_move_new(m), ply + 1)))
+}
+
+// few enough captures and checks that the unpruned tree stays small
+const NEGAMAX_POSITIONS: [&str; 8] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    // Rxd5 wins the queen
+    "4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1",
+    // only one pawn can get away, and quiescence skips the queen taking the other
+    "4k3/8/8/3q4/8/8/P5P1/4K3 w - - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 11",
+    "2r3k1/pp3ppp/4p3/3pP3/3P4/P4N2/1P3PPP/2R3K1 b - - 0 24",
+    "6k1/8/6K1/6P1/8/8/8/8 w - - 0 60",
+    "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1",
+    "k7/8/1K6/8/8/8/8/6R1 b - - 0 1",
+];
+
+// a one ply search prunes nothing beyond what quiescence skips, which the reference skips too,
+// so it has to agree with plain negamax, sign included
+#[test]
+fn search_matches_negamax() {
+    let engine = Engine::new();
+    for fen in NEGAMAX_POSITIONS {
+        let position = board(fen);
+        for position in [position, flip(&position)] {
+            let expected = MoveGen::new_legal(&position).map(|m| -reference_quiescence(position.make_move_new(m), 1)).max().unwrap();
+            assert_eq!(search(&engine, position, 1).score, expected, "{}", position);
+        }
+    }
+}
+
+// mates come out for the side to move at every ply, not just at the root
+#[test]
+fn mate_scores_are_for_the_side_to_move() {
+    let engine = Engine::new();
+    // back rank mate with Ra8
+    let mate_in_one = board("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+    // Kb8 is forced and Rg8 mates
+    let mated_in_one = board("k7/8/1K6/8/8/8/8/6R1 b - - 0 1");
+    for position in [mate_in_one, flip(&mate_in_one)] {
+        for depth in 1..=4 {
+            assert_eq!(search(&engine, position, depth).mate_in(), Some(1), "depth {} {}", depth, position);
+        }
+    }
+    for position in [mated_in_one, flip(&mated_in_one)] {
+        for depth in 2..=4 {
+            assert_eq!(search(&engine, position, depth).mate_in(), Some(-1), "depth {} {}", depth, position);
+        }
+    }
+}